@@ -1,8 +1,8 @@
-use rand::{
-    self,
-    distributions::{Distribution, Normal},
-    random,
-};
+use rand::random;
+
+use collection::{Review, StartingCard, LEARNING, RELEARNING, REVIEW};
+use daily::{self, Snapshot};
+use deck_state::{DeckState, MATURE_INTERVAL};
 
 pub struct AnkiSim {
     // State
@@ -14,10 +14,21 @@ pub struct AnkiSim {
     review_count: u32,
     lapse_count: u32,
     remove_lapse_count: u32,
+    expected_known: f64, // Sum of every card's recall probability today.
+    knowledge_days: f64, // Running total of expected_known.
+    next_card_id: i64,
+    revlog: Option<Revlog>, // Every answer so far, if recording.
 
     // Auto-calculated settings
     retention_ratio: f32, // Determined by interval_factor and measured_retention.
 
+    // Randomness.  Each card draws from its own stream, seeded from `seed`
+    // and the card's number, so runs with the same seed see the same
+    // difficulties and review outcomes card by card even when their
+    // settings differ.
+    seed: u64,
+    antithetic: bool, // Mirror every draw: u becomes 1 - u, z becomes -z.
+
     // Settings
     interval_factor: f32, // Multiplier for card intervals on "good" answer.
     lapse_interval_factor: f32, // Multiplier for card intervals on "again" answer.
@@ -27,6 +38,7 @@ pub struct AnkiSim {
     time_per_new_card: f32,
     time_per_review_card: f32,
     time_per_lapsed_card: f32,
+    deck_cards: Option<u32>, // Cards in a finite deck, or None for no end.
 }
 
 impl AnkiSim {
@@ -40,9 +52,16 @@ impl AnkiSim {
             review_count: 0,
             lapse_count: 0,
             remove_lapse_count: 0,
+            expected_known: 0.0,
+            knowledge_days: 0.0,
+            next_card_id: 1,
+            revlog: None,
 
             retention_ratio: 0.9,
 
+            seed: random(),
+            antithetic: false,
+
             interval_factor: 2.5,
             lapse_interval_factor: 0.0,
             measured_retention: (0.9, 2.5),
@@ -51,6 +70,7 @@ impl AnkiSim {
             time_per_new_card: 90.0,
             time_per_review_card: 20.0,
             time_per_lapsed_card: 40.0,
+            deck_cards: None,
         }
     }
 
@@ -63,6 +83,19 @@ impl AnkiSim {
             * self.measured_retention.0.ln()).exp();
     }
 
+    pub fn with_seed(self, seed: u64) -> Self {
+        let mut tmp = self;
+        tmp.seed = seed;
+        tmp
+    }
+
+    /// Mirrors every random draw, for the second run of an antithetic pair.
+    pub fn with_antithetic(self, antithetic: bool) -> Self {
+        let mut tmp = self;
+        tmp.antithetic = antithetic;
+        tmp
+    }
+
     pub fn with_difficulty_variance(self, variance: f32) -> Self {
         let mut tmp = self;
         tmp.difficulty_variance = variance;
@@ -117,18 +150,131 @@ impl AnkiSim {
         tmp
     }
 
+    /// Makes the deck finite: once this many cards have been added, no
+    /// more new cards come.
+    pub fn with_deck_cards(self, cards: Option<u32>) -> Self {
+        let mut tmp = self;
+        tmp.deck_cards = cards;
+        tmp
+    }
+
+    /// Records every answer in the schema of Anki's `revlog` table, with
+    /// day 1 of the simulation starting at `start` (Unix seconds).  Each
+    /// day's answers follow one another from the start of the day, taking
+    /// the configured seconds each.
+    pub fn with_revlog(self, start: i64) -> Self {
+        let mut tmp = self;
+        tmp.revlog = Some(Revlog {
+            start: start * 1000,
+            day: 0,
+            next_id: start * 1000,
+            reviews: Vec::new(),
+        });
+        tmp
+    }
+
+    /// The answers recorded so far, or none if not recording.
+    pub fn revlog(&self) -> &[Review] {
+        match self.revlog {
+            Some(ref log) => &log.reviews,
+            None => &[],
+        }
+    }
+
+    /// Records an answer to a card on the given day, if recording.  The
+    /// intervals are before and after the answer, with 0 for none.
+    fn log(
+        &mut self,
+        day: u32,
+        card: i64,
+        ease: i64,
+        intervals: (f32, f32),
+        seconds: f32,
+        kind: i64,
+    ) {
+        let factor = (self.interval_factor * 1000.0).round() as i64;
+        let log = match self.revlog {
+            Some(ref mut log) => log,
+            None => return,
+        };
+        // Each day's answers start when the day does, unless the last
+        // day's ran past its end.  Ids stay unique and increasing either
+        // way.
+        if log.day != day {
+            log.day = day;
+            let day_start = log.start + (day as i64 - 1) * 86_400_000;
+            log.next_id = log.next_id.max(day_start);
+        }
+        let time = (seconds * 1000.0).round() as i64;
+        log.reviews.push(Review {
+            id: log.next_id,
+            card,
+            ease,
+            interval: intervals.1.round() as i64,
+            last_interval: intervals.0.round() as i64,
+            factor,
+            time,
+            kind,
+        });
+        // Keep ids unique even for answers that take no time.
+        log.next_id += time.max(1);
+    }
+
+    /// Starts the deck with cards already studied, e.g. from a real Anki
+    /// collection.  These don't count as new cards.  Call this after the
+    /// other settings, since the cards' retention ratios depend on them.
+    pub fn with_starting_cards(self, cards: &[StartingCard]) -> Self {
+        let mut tmp = self;
+        for (i, c) in cards.iter().enumerate() {
+            // A separate family of streams from the new cards'.
+            let mut stream = Stream::new(mix_seed(!tmp.seed, i as u64), tmp.antithetic);
+            let retention_ratio =
+                tmp.retention_ratio as f64 + tmp.difficulty_variance as f64 * stream.normal();
+            let mut card = Card {
+                id: tmp.next_card_id,
+                interval: c.interval,
+                days_since_last_review: c.days_since_last_review,
+                retention_ratio: retention_ratio.clamp(0.01, 0.99) as f32,
+                lapses: c.lapses,
+                recall: 1.0,
+                decay: 1.0,
+                stream,
+            };
+            card.reset_recall();
+            card.recall = card.decay.powf(card.days_since_last_review);
+            tmp.next_card_id += 1;
+            tmp.deck.push(card);
+        }
+        tmp
+    }
+
     /// Adds N new cards to the deck.
     pub fn add_new_cards(&mut self, n: u32) {
         for _ in 0..n {
             self.time_spent_on_new += self.time_per_new_card;
+            let mut stream = Stream::new(
+                mix_seed(self.seed, self.cards_added as u64),
+                self.antithetic,
+            );
             self.cards_added += 1;
-            let sampler = Normal::new(self.retention_ratio as f64, self.difficulty_variance as f64);
-            self.deck.push(Card {
+            let retention_ratio =
+                self.retention_ratio as f64 + self.difficulty_variance as f64 * stream.normal();
+            let mut card = Card {
+                id: self.next_card_id,
                 interval: 1.0,
                 days_since_last_review: 0.0,
-                retention_ratio: sampler.sample(&mut rand::thread_rng()).max(0.01).min(0.99) as f32,
+                retention_ratio: retention_ratio.clamp(0.01, 0.99) as f32,
                 lapses: 0,
-            });
+                recall: 1.0,
+                decay: 1.0,
+                stream,
+            };
+            card.reset_recall();
+            self.next_card_id += 1;
+            let day = self.days_past + 1;
+            let time = self.time_per_new_card;
+            self.log(day, card.id, 3, (0.0, 1.0), time, LEARNING);
+            self.deck.push(card);
         }
     }
 
@@ -137,48 +283,120 @@ impl AnkiSim {
         self.days_past += 1;
 
         // Do scheduled reviews.
+        let mut expected_known = 0.0;
         let mut i = 0;
         while i < self.deck.len() {
             if self.deck[i].days_since_last_review >= self.deck[i].interval {
                 // Do review.
+                let (day, id, last) = (self.days_past, self.deck[i].id, self.deck[i].interval);
+                let (review_time, lapsed_time) =
+                    (self.time_per_review_card, self.time_per_lapsed_card);
                 self.review_count += 1;
                 self.time_spent_on_review += self.time_per_review_card;
                 if self.deck[i].is_remembered() {
                     // Good
                     self.deck[i].interval *= self.interval_factor;
-                    self.deck[i].interval += (random::<f32>() - 0.5) * self.deck[i].interval * 0.2;
+                    let fuzz = self.deck[i].stream.uniform() - 0.5;
+                    self.deck[i].interval += fuzz * self.deck[i].interval * 0.2;
                     self.deck[i].days_since_last_review = 0.0;
+                    self.deck[i].reset_recall();
+                    let next = self.deck[i].interval;
+                    self.log(day, id, 3, (last, next), review_time, REVIEW);
                 } else if self.deck[i].lapses < self.max_lapses {
                     // Normal lapse
                     self.deck[i].interval =
                         (self.deck[i].interval * self.lapse_interval_factor).max(1.0);
                     self.deck[i].days_since_last_review = 0.0;
+                    self.deck[i].reset_recall();
                     self.deck[i].lapses += 1;
                     self.lapse_count += 1;
                     self.time_spent_on_review += self.time_per_lapsed_card;
+                    let next = self.deck[i].interval;
+                    self.log(day, id, 1, (last, next), review_time, REVIEW);
+                    self.log(day, id, 3, (next, next), lapsed_time, RELEARNING);
                 } else {
                     // Lapsed past max lapses
                     self.deck.swap_remove(i);
                     self.lapse_count += 1;
                     self.remove_lapse_count += 1;
+                    self.log(day, id, 1, (last, 0.0), review_time, REVIEW);
                     continue;
                 }
             } else {
                 // Not scheduled for review today.
                 self.deck[i].days_since_last_review += 1.0;
+                self.deck[i].recall *= self.deck[i].decay;
             }
+            expected_known += self.deck[i].recall as f64;
             i += 1;
         }
+        self.expected_known = expected_known;
+        self.knowledge_days += expected_known;
     }
 
     /// Simulates multiple days.
     pub fn simulate_n_days(&mut self, n: u32, new_card_per_day: u32) {
+        self.simulate_n_days_with(n, new_card_per_day, |_| {});
+    }
+
+    /// Simulates multiple days, calling `on_day` at the end of each.
+    pub fn simulate_n_days_with<F: FnMut(&Self)>(
+        &mut self,
+        n: u32,
+        new_card_per_day: u32,
+        mut on_day: F,
+    ) {
         for _ in 0..n {
-            self.add_new_cards(new_card_per_day);
+            let remaining = match self.deck_cards {
+                Some(cards) => cards.saturating_sub(self.cards_added),
+                None => new_card_per_day,
+            };
+            self.add_new_cards(new_card_per_day.min(remaining));
             self.simulate_day();
+            on_day(self);
         }
     }
 
+    /// Running totals and the state of the deck.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot {
+            cards_added: self.cards_added as f64,
+            reviews: self.review_count as f64,
+            lapses: self.lapse_count as f64,
+            removed: self.remove_lapse_count as f64,
+            new_seconds: self.time_spent_on_new as f64,
+            review_seconds: self.time_spent_on_review as f64,
+            deck_size: self.deck.len() as f64,
+            known_cards: self.known_cards(),
+            ..Snapshot::default()
+        };
+        for card in &self.deck {
+            if card.days_since_last_review >= card.interval {
+                snapshot.due += 1.0;
+            }
+            snapshot.interval_counts[daily::bucket(card.interval)] += 1.0;
+        }
+        snapshot
+    }
+
+    /// Histograms of the deck, and when its cards are next due over the
+    /// following `forecast_days`.
+    pub fn deck_state(&self, forecast_days: u32) -> DeckState {
+        let mut state = DeckState::new(self.days_past, self.max_lapses, forecast_days);
+        for card in &self.deck {
+            state.add_cards(
+                1.0,
+                card.interval,
+                card.days_since_last_review,
+                card.lapses,
+                card.retention_ratio,
+            );
+        }
+        state.mature = self.cards_with_interval_or_greater(MATURE_INTERVAL) as f64;
+        state.young = self.deck.len() as f64 - state.mature;
+        state
+    }
+
     /// Number of cards with the given interval or greater.
     fn cards_with_interval_or_greater(&self, interval: u32) -> u32 {
         let mut count = 0;
@@ -190,9 +408,30 @@ impl AnkiSim {
         count
     }
 
-    pub fn known_cards(&self) -> u32 {
+    /// Expected known cards summed over every day so far.
+    pub fn knowledge_days(&self) -> f64 {
+        self.knowledge_days
+    }
+
+    /// Knowledge-days per hour spent on reviews and new cards.
+    pub fn knowledge_days_per_hour(&self) -> f32 {
+        (self.knowledge_days / (self.time_spent_on_new + self.time_spent_on_review) as f64 * 3600.0)
+            as f32
+    }
+
+    /// Cards known at the end of the day: the sum of every card's recall
+    /// probability, given its own retention ratio and how long ago it was
+    /// last reviewed.
+    pub fn known_cards(&self) -> f64 {
+        self.expected_known
+    }
+
+    /// Known cards from the deck size alone, assuming every card has the
+    /// average retention ratio and is equally likely to be anywhere in its
+    /// interval.  Kept for comparison with `known_cards()`.
+    pub fn closed_form_known_cards(&self) -> f64 {
         let retained = -(1.0 - self.retention_ratio) / self.retention_ratio.ln();
-        (self.deck.len() as f64 * retained as f64) as u32
+        self.deck.len() as f64 * retained as f64
     }
 
     /// Calculates the number of cards learned per hour spent on reviews and new cards.
@@ -216,16 +455,155 @@ impl AnkiSim {
     }
 }
 
+/// Answers recorded in Anki's `revlog` schema.
+struct Revlog {
+    start: i64, // Unix milliseconds when day 1 starts.
+    day: u32,
+    next_id: i64, // When the next answer starts, in Unix milliseconds.
+    reviews: Vec<Review>,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct Card {
+    id: i64, // Numbered from 1 in the order cards join the deck.
     interval: f32,
     days_since_last_review: f32,
     retention_ratio: f32, // Chance that the card will be remembered each review.
     lapses: u32,
+    // Chance of recalling the card now, and the factor it falls by each
+    // day.  Memory decays exponentially, to the card's retention ratio by
+    // the time it's due.
+    recall: f32,
+    decay: f32,
+    stream: Stream,
 }
 
 impl Card {
-    fn is_remembered(&self) -> bool {
-        random::<f32>() < self.retention_ratio
+    fn is_remembered(&mut self) -> bool {
+        self.stream.uniform() < self.retention_ratio
+    }
+
+    /// Restarts the card's memory decay after a review.
+    fn reset_recall(&mut self) {
+        self.recall = 1.0;
+        self.decay = self.retention_ratio.powf(1.0 / self.interval);
+    }
+}
+
+/// Combines two numbers into a well-mixed seed.
+pub fn mix_seed(a: u64, b: u64) -> u64 {
+    splitmix(a ^ splitmix(b.wrapping_add(GOLDEN_GAMMA)))
+}
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64's output function.
+fn splitmix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A card's own stream of random numbers (SplitMix64).
+#[derive(Debug, Copy, Clone)]
+struct Stream {
+    state: u64,
+    antithetic: bool,
+}
+
+impl Stream {
+    fn new(seed: u64, antithetic: bool) -> Stream {
+        Stream {
+            state: seed,
+            antithetic,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        splitmix(self.state)
+    }
+
+    /// Uniform in [0, 1), or its mirror image when antithetic.
+    fn uniform(&mut self) -> f32 {
+        let u = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        if self.antithetic {
+            1.0 - u
+        } else {
+            u
+        }
+    }
+
+    /// Standard normal by Box-Muller, negated when antithetic.
+    fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        let u2 = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * u2).cos();
+        if self.antithetic {
+            -z
+        } else {
+            z
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(seed: u64, antithetic: bool) -> AnkiSim {
+        let mut sim = AnkiSim::new().with_seed(seed).with_antithetic(antithetic);
+        sim.simulate_n_days(60, 10);
+        sim
+    }
+
+    #[test]
+    fn revlog_ids_increase_under_heavy_loads() {
+        // A day of 2000 new cards at 90 seconds each takes 50 hours.
+        let mut sim = AnkiSim::new().with_seed(5).with_revlog(1_000_000);
+        sim.simulate_n_days(3, 2000);
+        let reviews = sim.revlog();
+        assert_eq!(reviews[0].id, 1_000_000_000);
+        assert!(reviews.last().unwrap().id > 1_000_000_000 + 3 * 86_400_000);
+        for pair in reviews.windows(2) {
+            assert!(pair[0].id < pair[1].id);
+        }
+
+        // Which SQLite would refuse as duplicate primary keys otherwise.
+        let path = ::std::env::temp_dir().join("anki_sim_test_revlog.anki2");
+        let path = path.to_str().unwrap();
+        let _ = ::std::fs::remove_file(path);
+        let written = ::collection::write_revlog(path, reviews);
+        let loaded = ::collection::load_revlog(path);
+        ::std::fs::remove_file(path).unwrap();
+        written.unwrap();
+        assert_eq!(loaded.unwrap().len(), reviews.len());
+    }
+
+    #[test]
+    fn streams_mirror_when_antithetic() {
+        let (mut a, mut b) = (Stream::new(3, false), Stream::new(3, true));
+        for _ in 0..100 {
+            let u = a.uniform();
+            assert!((0.0..1.0).contains(&u));
+            assert_eq!(b.uniform(), 1.0 - u);
+        }
+        let (mut a, mut b) = (Stream::new(3, false), Stream::new(3, true));
+        assert_eq!(a.normal(), -b.normal());
+    }
+
+    #[test]
+    fn seeds_mix_both_inputs() {
+        assert_ne!(mix_seed(1, 2), mix_seed(2, 1));
+        assert_ne!(mix_seed(1, 2), mix_seed(1, 3));
+        assert_eq!(mix_seed(1, 2), mix_seed(1, 2));
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let (a, b) = (run(11, false), run(11, false));
+        assert_eq!(a.review_count, b.review_count);
+        assert_eq!(a.cards_learned_per_hour(), b.cards_learned_per_hour());
+        assert_ne!(a.review_count, run(11, true).review_count);
     }
 }
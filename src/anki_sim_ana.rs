@@ -1,15 +1,51 @@
+use std::collections::HashMap;
+
+use collection::StartingCard;
+use daily::{self, Snapshot};
+use deck_state::{DeckState, MATURE_INTERVAL};
+
 /// Anki sim using analytics to run faster, but accounting
 /// for fewer things.
+///
+/// Only the cohort of cards added on the first day is simulated.  Every
+/// day's cohort behaves the same, just shifted in time, so the whole deck
+/// is the sum of the cohort at each age it has reached.  A finite deck
+/// runs out of new cards, so its last cohort may be partial and later days
+/// have none.
+///
+/// Cards the deck starts with are simulated separately, as a single
+/// cohort of their own that is never repeated.
+#[derive(Clone)]
 pub struct AnkiSim {
     // State
-    deck: Vec<CardCluster>,
+    deck: Vec<CardCluster>, // The first day's cohort.
     cards_added: f64,
     days_past: u32,
     time_spent_on_new: f64,
+    // Running totals of the cohort.
     time_spent_on_review: f64,
     review_count: f64,
     lapse_count: f64,
     remove_lapse_count: f64,
+    // The cohort's running totals at each age so far, and the size of each
+    // day's cohort relative to a full day of new cards.
+    history: Vec<Totals>,
+    cohort_sizes: Vec<f64>,
+    // The cohort's expected known cards, grouped by interval, since all
+    // clusters with the same interval forget at the same rate.
+    recall_groups: Vec<RecallGroup>,
+    recall_group_index: HashMap<u32, usize>, // By the interval's bits.
+    // The cohort's expected known cards today and at each age, and the
+    // whole deck's today and summed over every day so far.
+    cohort_known: f64,
+    expected_history: Vec<f64>,
+    expected_known: f64,
+    knowledge_days: f64,
+    // Running totals of the whole deck: the sums, over every day so far, of
+    // what every cohort did on that day.
+    deck_totals: Totals,
+    // The cards the deck started with, if any.
+    starting: Option<Box<AnkiSim>>,
 
     // Auto-calculated settings
     retention_ratio: f32, // Determined by interval_factor and measured_retention.
@@ -23,6 +59,8 @@ pub struct AnkiSim {
     time_per_new_card: f32,
     time_per_review_card: f32,
     time_per_lapsed_card: f32,
+    new_cards_per_day: f64,
+    deck_cards: Option<f64>, // Cards in a finite deck, or None for no end.
 }
 
 impl AnkiSim {
@@ -36,6 +74,16 @@ impl AnkiSim {
             review_count: 0.0,
             lapse_count: 0.0,
             remove_lapse_count: 0.0,
+            history: Vec::new(),
+            cohort_sizes: Vec::new(),
+            recall_groups: Vec::new(),
+            recall_group_index: HashMap::new(),
+            cohort_known: 0.0,
+            expected_history: Vec::new(),
+            expected_known: 0.0,
+            knowledge_days: 0.0,
+            deck_totals: Totals::default(),
+            starting: None,
 
             retention_ratio: 0.9,
 
@@ -47,6 +95,8 @@ impl AnkiSim {
             time_per_new_card: 90.0,
             time_per_review_card: 20.0,
             time_per_lapsed_card: 40.0,
+            new_cards_per_day: 1.0,
+            deck_cards: None,
         }
     }
 
@@ -111,9 +161,79 @@ impl AnkiSim {
         tmp
     }
 
+    /// Number of new cards added each day.  Since the simulation is
+    /// analytical this only scales the results, but it makes them directly
+    /// comparable with the Monte Carlo sim.
+    pub fn with_new_cards_per_day(self, n: f64) -> Self {
+        let mut tmp = self;
+        tmp.new_cards_per_day = n;
+        tmp
+    }
+
+    /// Makes the deck finite: once this many cards have been added, no
+    /// more new cards come.
+    pub fn with_deck_cards(self, cards: Option<f64>) -> Self {
+        let mut tmp = self;
+        tmp.deck_cards = cards;
+        tmp
+    }
+
+    /// Starts the deck with cards already studied, e.g. from a real Anki
+    /// collection.  These don't count as new cards.  Call this after the
+    /// other settings, which the starting cards are simulated with.
+    pub fn with_starting_cards(self, cards: &[StartingCard]) -> Self {
+        let mut tmp = self;
+        tmp.starting = None;
+        if cards.is_empty() {
+            return tmp;
+        }
+
+        // A finite deck of one day's worth of cards, which are these, with
+        // no time spent on them as new cards.
+        let n = cards.len() as f64;
+        let mut starting = AnkiSim {
+            time_per_new_card: 0.0,
+            new_cards_per_day: n,
+            deck_cards: Some(n),
+            ..tmp.clone()
+        };
+        let mut clusters = HashMap::new();
+        for c in cards {
+            let key = (
+                c.interval.to_bits(),
+                c.days_since_last_review.to_bits(),
+                c.lapses,
+            );
+            let deck = &mut starting.deck;
+            let i = *clusters.entry(key).or_insert_with(|| {
+                deck.push(CardCluster {
+                    interval: c.interval,
+                    days_since_last_review: c.days_since_last_review,
+                    lapses: c.lapses,
+                    card_count: 0.0,
+                });
+                deck.len() - 1
+            });
+            deck[i].card_count += 1.0;
+        }
+        for i in 0..starting.deck.len() {
+            let cluster = starting.deck[i];
+            starting.track_recall(
+                cluster.interval,
+                cluster.card_count,
+                cluster.days_since_last_review,
+            );
+        }
+        tmp.starting = Some(Box::new(starting));
+        tmp
+    }
+
     /// Simulates a single day.
     fn simulate_day(&mut self) {
         self.days_past += 1;
+        for group in &mut self.recall_groups {
+            group.known *= group.decay;
+        }
 
         // Do scheduled reviews.
         let mut i = 0;
@@ -121,7 +241,7 @@ impl AnkiSim {
         while i < deck_size {
             if self.deck[i].days_since_last_review >= self.deck[i].interval {
                 // Update review stats.
-                self.review_count += self.deck[i].card_count as f64;
+                self.review_count += self.deck[i].card_count;
                 self.time_spent_on_review +=
                     self.time_per_review_card as f64 * self.deck[i].card_count;
 
@@ -130,6 +250,13 @@ impl AnkiSim {
                 let lapse_interval = (self.deck[i].interval * self.lapse_interval_factor).max(1.0);
                 let good_card_count = self.deck[i].card_count * self.retention_ratio as f64;
                 let lapse_card_count = self.deck[i].card_count - good_card_count;
+                let cluster = self.deck[i];
+                self.end_recall(
+                    cluster.interval,
+                    cluster.card_count,
+                    cluster.days_since_last_review + 1.0,
+                );
+                self.start_recall(good_interval, good_card_count);
 
                 // Update good.
                 self.deck[i].interval = good_interval;
@@ -146,6 +273,7 @@ impl AnkiSim {
                         card_count: lapse_card_count,
                     };
                     self.deck.push(lapse_card);
+                    self.start_recall(lapse_interval, lapse_card_count);
                     self.lapse_count += lapse_card_count;
                     self.time_spent_on_review +=
                         self.time_per_lapsed_card as f64 * lapse_card_count;
@@ -160,53 +288,264 @@ impl AnkiSim {
             }
             i += 1;
         }
+
+        // Rounding can leave a group that has been emptied slightly
+        // negative.
+        self.cohort_known = self.recall_groups.iter().map(|g| g.known.max(0.0)).sum();
+    }
+
+    /// The group of clusters with `interval`, created if needed.
+    fn recall_group(&mut self, interval: f32) -> &mut RecallGroup {
+        let groups = &mut self.recall_groups;
+        let retention_ratio = self.retention_ratio as f64;
+        let i = *self
+            .recall_group_index
+            .entry(interval.to_bits())
+            .or_insert_with(|| {
+                // Memory decays exponentially, to the retention ratio by the
+                // time the cards are due.
+                groups.push(RecallGroup {
+                    decay: retention_ratio.powf(1.0 / interval as f64),
+                    known: 0.0,
+                });
+                groups.len() - 1
+            });
+        &mut self.recall_groups[i]
+    }
+
+    /// Cards just reviewed: they are certain to be recalled today.
+    fn start_recall(&mut self, interval: f32, cards: f64) {
+        self.track_recall(interval, cards, 0.0);
+    }
+
+    /// Cards `days` after their last review join their group.
+    fn track_recall(&mut self, interval: f32, cards: f64, days: f32) {
+        let group = self.recall_group(interval);
+        group.known += cards * group.decay.powf(days as f64);
+    }
+
+    /// Cards about to be reviewed, `days` after their last review, leave
+    /// their group.
+    fn end_recall(&mut self, interval: f32, cards: f64, days: f32) {
+        let group = self.recall_group(interval);
+        group.known -= cards * group.decay.powf(days as f64);
     }
 
     /// Simulates multiple days.
     pub fn simulate_n_days(&mut self, n: u32) {
-        if self.cards_added == 0.0 {
+        self.simulate_n_days_with(n, |_| {});
+    }
+
+    /// Simulates multiple days, calling `on_day` at the end of each.
+    pub fn simulate_n_days_with<F: FnMut(&Self)>(&mut self, n: u32, mut on_day: F) {
+        if self.days_past == 0 && self.deck.is_empty() {
             self.deck.push(CardCluster {
                 interval: 1.0,
                 days_since_last_review: 0.0,
                 lapses: 0,
-                card_count: 1.0,
-            })
+                card_count: self.new_cards_per_day,
+            });
+            let cards = self.new_cards_per_day;
+            self.start_recall(1.0, cards);
         }
 
-        let mut review_time_acc = 0.0;
-        let mut review_count_acc = 0.0;
-        let mut lapse_count_acc = 0.0;
-        let mut remove_lapse_count_acc = 0.0;
-
         for _ in 0..n {
-            self.cards_added += 1.0;
-            self.time_spent_on_new += self.time_per_new_card as f64;
+            let added = match self.deck_cards {
+                Some(cards) => self.new_cards_per_day.min((cards - self.cards_added).max(0.0)),
+                None => self.new_cards_per_day,
+            };
+            self.cards_added += added;
+            self.time_spent_on_new += self.time_per_new_card as f64 * added;
+            self.cohort_sizes.push(if self.new_cards_per_day > 0.0 {
+                added / self.new_cards_per_day
+            } else {
+                0.0
+            });
             self.simulate_day();
-            review_time_acc += self.time_spent_on_review;
-            review_count_acc += self.review_count;
-            lapse_count_acc += self.lapse_count;
-            remove_lapse_count_acc += self.remove_lapse_count;
+            self.history.push(self.cohort_totals());
+
+            let today = self.last_day();
+            self.deck_totals.add(&today, 1.0);
+
+            self.expected_history.push(self.cohort_known);
+            self.expected_known = match self.deck_cards {
+                // One full cohort of each age.
+                None => self.expected_known + self.cohort_known,
+                Some(_) => self
+                    .cohort_sizes
+                    .iter()
+                    .zip(self.expected_history.iter().rev())
+                    .map(|(size, known)| size * known)
+                    .sum(),
+            };
+            self.knowledge_days += self.expected_known;
+
+            if let Some(ref mut starting) = self.starting {
+                starting.simulate_n_days(1);
+            }
+            on_day(self);
+        }
+    }
+
+    /// Running totals of the first day's cohort of new cards alone.
+    pub fn cohort_totals(&self) -> Totals {
+        Totals {
+            reviews: self.review_count,
+            lapses: self.lapse_count,
+            removed: self.remove_lapse_count,
+            review_seconds: self.time_spent_on_review,
+        }
+    }
+
+    /// What the whole deck did on the most recent day: each cohort added so
+    /// far did what the first did at the same age.  Without an end to new
+    /// cards that sums to the cohort's running totals.
+    pub fn last_day(&self) -> Totals {
+        if self.deck_cards.is_none() {
+            return self.cohort_totals();
+        }
+        let days = self.history.len();
+        let mut today = Totals::default();
+        for (j, &size) in self.cohort_sizes.iter().enumerate() {
+            if size == 0.0 {
+                continue;
+            }
+            // Day j + 1's cohort is days - j days old.
+            let age = days - j;
+            today.add(&self.history[age - 1], size);
+            if age > 1 {
+                today.add(&self.history[age - 2], -size);
+            }
+        }
+        today
+    }
+
+    /// The size of each day's cohort, relative to a full day of new cards.
+    pub fn cohort_sizes(&self) -> &[f64] {
+        &self.cohort_sizes
+    }
+
+    /// Running totals of the whole deck over its lifetime, with new cards
+    /// added every day: the sum of every cohort's running totals so far.
+    /// These are what the end-of-run metrics are based on.  They include
+    /// the starting cards.
+    pub fn deck_totals(&self) -> Totals {
+        let mut totals = self.deck_totals;
+        if let Some(ref starting) = self.starting {
+            totals.add(&starting.deck_totals, 1.0);
+        }
+        totals
+    }
+
+    /// The deck's lifetime totals averaged over every card it has had, so
+    /// that each card counts once however long it has been in the deck.
+    /// Zero before any cards are added.
+    pub fn per_card_totals(&self) -> Totals {
+        let cards = self.cards_added + self.starting.as_ref().map_or(0.0, |s| s.cards_added);
+        let mut totals = Totals::default();
+        if cards > 0.0 {
+            totals.add(&self.deck_totals(), 1.0 / cards);
+        }
+        totals
+    }
+
+    /// Running totals and state of the cards the deck started with, for
+    /// adding to the superposed snapshots.  Starting cards aren't new, so
+    /// none are counted as added.
+    pub fn starting_snapshot(&self) -> Option<Snapshot> {
+        self.starting.as_ref().map(|s| Snapshot {
+            cards_added: 0.0,
+            ..s.snapshot()
+        })
+    }
+
+    /// Histograms of the cards the deck started with, for adding to the
+    /// superposed deck state.
+    pub fn starting_deck_state(&self, forecast_days: u32) -> Option<DeckState> {
+        self.starting.as_ref().map(|s| s.deck_state(forecast_days))
+    }
+
+    /// Running totals and state of the one day's cohort of new cards that
+    /// the sim follows.  `daily::superpose()` turns these into the whole
+    /// deck's.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot {
+            cards_added: self.new_cards_per_day,
+            reviews: self.review_count,
+            lapses: self.lapse_count,
+            removed: self.remove_lapse_count,
+            new_seconds: self.time_per_new_card as f64 * self.new_cards_per_day,
+            review_seconds: self.time_spent_on_review,
+            known_cards: self.cohort_known,
+            ..Snapshot::default()
+        };
+        for cluster in &self.deck {
+            snapshot.deck_size += cluster.card_count;
+            if cluster.days_since_last_review >= cluster.interval {
+                snapshot.due += cluster.card_count;
+            }
+            snapshot.interval_counts[daily::bucket(cluster.interval)] += cluster.card_count;
         }
+        snapshot
+    }
 
-        self.time_spent_on_review = review_time_acc;
-        self.review_count = review_count_acc;
-        self.lapse_count = lapse_count_acc;
-        self.remove_lapse_count = remove_lapse_count_acc;
+    /// Histograms of the first day's cohort, and when its cards are next
+    /// due over the following `forecast_days`.  Combine these with
+    /// `deck_state::superpose()` for the whole deck.
+    pub fn deck_state(&self, forecast_days: u32) -> DeckState {
+        let mut state = DeckState::new(self.days_past, self.max_lapses, forecast_days);
+        for cluster in &self.deck {
+            state.add_cards(
+                cluster.card_count,
+                cluster.interval,
+                cluster.days_since_last_review,
+                cluster.lapses,
+                self.retention_ratio,
+            );
+            if cluster.interval >= MATURE_INTERVAL as f32 {
+                state.mature += cluster.card_count;
+            } else {
+                state.young += cluster.card_count;
+            }
+        }
+        state
     }
 
+    /// Cards in the whole deck expected to be recalled at the end of the
+    /// day, from each cluster's recall probability.  The Monte Carlo
+    /// engine's `known_cards()` are counted the same way.
     pub fn known_cards(&self) -> f64 {
+        self.expected_known + self.starting.as_ref().map_or(0.0, |s| s.expected_known)
+    }
+
+    /// Expected known cards summed over every day so far.
+    pub fn knowledge_days(&self) -> f64 {
+        self.knowledge_days + self.starting.as_ref().map_or(0.0, |s| s.knowledge_days)
+    }
+
+    /// Knowledge-days per hour spent on reviews and new cards.
+    pub fn knowledge_days_per_hour(&self) -> f32 {
+        (self.knowledge_days() / (self.time_spent_on_new + self.deck_totals().review_seconds)
+            * 3600.0) as f32
+    }
+
+    /// Known cards from the deck size and average retention alone.  Kept
+    /// for comparison with `known_cards()`.
+    pub fn closed_form_known_cards(&self) -> f64 {
         let retained = -(1.0 - self.retention_ratio) / self.retention_ratio.ln();
-        (self.cards_added - self.remove_lapse_count) * retained as f64
+        let cards = self.cards_added + self.starting.as_ref().map_or(0.0, |s| s.cards_added);
+        (cards - self.deck_totals().removed) * retained as f64
     }
 
     /// Calculates the number of cards learned per hour spent on reviews and new cards.
     pub fn cards_learned_per_hour(&self) -> f32 {
-        (self.known_cards() / (self.time_spent_on_new + self.time_spent_on_review) * 3600.0) as f32
+        (self.known_cards() / (self.time_spent_on_new + self.deck_totals().review_seconds) * 3600.0)
+            as f32
     }
 
     /// In hours.
     pub fn review_time(&self) -> f32 {
-        (self.time_spent_on_review / 3600.0) as f32
+        (self.deck_totals().review_seconds / 3600.0) as f32
     }
 
     /// In hours.
@@ -215,10 +554,39 @@ impl AnkiSim {
     }
 
     pub fn lapses_per_review(&self) -> f32 {
-        (self.lapse_count / self.review_count) as f32
+        let totals = self.deck_totals();
+        (totals.lapses / totals.reviews) as f32
+    }
+}
+
+/// Review counts and time, in cards (fractional) and seconds.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Totals {
+    pub reviews: f64,
+    pub lapses: f64,
+    /// Cards dropped for lapsing past the maximum.
+    pub removed: f64,
+    pub review_seconds: f64,
+}
+
+impl Totals {
+    /// Adds `weight` times `other`.
+    pub fn add(&mut self, other: &Totals, weight: f64) {
+        self.reviews += other.reviews * weight;
+        self.lapses += other.lapses * weight;
+        self.removed += other.removed * weight;
+        self.review_seconds += other.review_seconds * weight;
     }
 }
 
+/// Clusters sharing an interval, whose recall probabilities all fall by
+/// the same factor each day.
+#[derive(Debug, Copy, Clone)]
+struct RecallGroup {
+    decay: f64,
+    known: f64, // Expected known cards in the group's clusters.
+}
+
 #[derive(Debug, Copy, Clone)]
 struct CardCluster {
     interval: f32,
@@ -237,3 +605,64 @@ impl CardCluster {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(sim: &AnkiSim) -> [f64; 7] {
+        let t = sim.deck_totals();
+        [
+            t.reviews,
+            t.lapses,
+            t.removed,
+            t.review_seconds,
+            sim.known_cards(),
+            sim.closed_form_known_cards(),
+            sim.knowledge_days(),
+        ]
+    }
+
+    fn assert_split_runs_match(sim: AnkiSim) {
+        let mut whole = sim.clone();
+        whole.simulate_n_days(50);
+        let mut split = sim;
+        split.simulate_n_days(20);
+        split.simulate_n_days(30);
+        for (a, b) in totals(&whole).iter().zip(totals(&split).iter()) {
+            assert!((a - b).abs() <= 1e-9 * a.abs().max(1.0), "{} != {}", a, b);
+        }
+        assert!(totals(&whole)[0] > 0.0);
+    }
+
+    #[test]
+    fn split_runs_match_whole_runs() {
+        assert_split_runs_match(AnkiSim::new().with_new_cards_per_day(10.0));
+    }
+
+    #[test]
+    fn split_runs_of_finite_decks_match_whole_runs() {
+        // The deck runs out partway through the first run.
+        assert_split_runs_match(
+            AnkiSim::new()
+                .with_new_cards_per_day(10.0)
+                .with_deck_cards(Some(155.0)),
+        );
+    }
+
+    #[test]
+    fn deck_totals_sum_the_cohorts() {
+        let mut sim = AnkiSim::new().with_new_cards_per_day(10.0);
+        assert_eq!(sim.per_card_totals().reviews, 0.0);
+        let mut summed = Totals::default();
+        sim.simulate_n_days_with(60, |s| summed.add(&s.cohort_totals(), 1.0));
+
+        let (deck, per_card) = (sim.deck_totals(), sim.per_card_totals());
+        assert!((deck.reviews - summed.reviews).abs() < 1e-9 * summed.reviews);
+        assert!((deck.review_seconds - summed.review_seconds).abs() < 1e-9 * summed.review_seconds);
+        assert!((per_card.reviews * 600.0 - deck.reviews).abs() < 1e-9 * deck.reviews);
+        // The first cohort's cards are the oldest, so had the most reviews.
+        let first = sim.cohort_totals().reviews / 10.0;
+        assert!(per_card.reviews > 0.0 && per_card.reviews < first);
+    }
+}
@@ -0,0 +1,480 @@
+//! Fitting simulation settings to a learner's real Anki review history:
+//! the forgetting curve and the time each kind of answer takes.
+
+use std::str::FromStr;
+
+use collection::{Review, LEARNING, RELEARNING, REVIEW};
+use deck_state;
+use stats::{summarize, Summary};
+
+/// A review that followed a successful review, as the simulation's
+/// forgetting model sees it.
+#[derive(Debug, Copy, Clone)]
+pub struct Observation {
+    /// Days since the previous review over the days between the two before
+    /// it: the interval factor the card was actually reviewed at.
+    pub factor: f64,
+    pub recalled: bool,
+}
+
+/// Reviews shorter than this many days after the one before don't count
+/// as spaced reviews, e.g. same-day repeats.
+const MIN_ELAPSED_DAYS: f64 = 0.5;
+
+/// Pairs each review with how far its interval stretched the previous one.
+///
+/// Both engines take a review's chance of recall to depend only on the
+/// interval factor, so only reviews that follow a successful spaced review
+/// count: after a lapse or a learning step there's no previous interval to
+/// stretch.  `reviews` must be ordered by card and then time, as
+/// `collection::load_revlog()` gives them.
+pub fn observations(reviews: &[Review]) -> Vec<Observation> {
+    let mut observations = Vec::new();
+    for window in reviews.windows(3) {
+        let (before, previous, review) = (&window[0], &window[1], &window[2]);
+        if before.card != review.card || previous.card != review.card {
+            continue;
+        }
+        if review.kind != REVIEW || previous.kind != REVIEW || !previous.recalled() {
+            continue;
+        }
+        let last = previous.day() - before.day();
+        let elapsed = review.day() - previous.day();
+        if last < MIN_ELAPSED_DAYS || elapsed < MIN_ELAPSED_DAYS {
+            continue;
+        }
+        observations.push(Observation {
+            factor: elapsed / last,
+            recalled: review.recalled(),
+        });
+    }
+    observations
+}
+
+/// The forgetting curve fitted to a review history.
+///
+/// The engines' model is `p = exp(-rate * factor)`: memory decays
+/// exponentially, at a speed set by the interval the card last survived.
+/// The retention measured at one interval factor then fixes it at every
+/// other, which is what `measured_retention` and
+/// `measured_retention_interval` express.
+#[derive(Debug, Clone)]
+pub struct RetentionFit {
+    pub rate: f64,
+    pub rate_std_err: f64,
+    pub observations: usize,
+    /// The fraction of observations recalled.
+    pub observed_retention: f64,
+    pub mean_factor: f64,
+    pub log_likelihood: f64,
+    /// The log-likelihood of the same recall rate for every review.
+    pub null_log_likelihood: f64,
+    /// The mean squared difference between outcome and prediction.
+    pub brier_score: f64,
+    /// Observations grouped by interval factor, for checking the fit.
+    pub bins: Vec<CalibrationBin>,
+    /// The Hosmer-Lemeshow statistic over the bins, and its degrees of
+    /// freedom.  A statistic well above its degrees of freedom means the
+    /// curve's shape doesn't match the data.
+    pub chi_squared: f64,
+    pub degrees_of_freedom: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CalibrationBin {
+    pub min_factor: f64,
+    pub max_factor: f64,
+    pub count: usize,
+    pub observed: f64,
+    pub predicted: f64,
+}
+
+/// Bins for the calibration table and goodness of fit.
+const CALIBRATION_BINS: usize = 10;
+
+impl RetentionFit {
+    /// The fitted retention at an interval factor.
+    pub fn retention(&self, factor: f64) -> f64 {
+        (-self.rate * factor).exp()
+    }
+
+    /// A 95% confidence interval for `retention(factor)`.
+    pub fn retention_ci(&self, factor: f64) -> (f64, f64) {
+        let reach = 1.96 * self.rate_std_err;
+        (
+            (-(self.rate + reach) * factor).exp(),
+            (-(self.rate - reach).max(0.0) * factor).exp(),
+        )
+    }
+}
+
+/// Fits the forgetting rate by maximum likelihood.
+pub fn fit_retention(observations: &[Observation]) -> Result<RetentionFit, String> {
+    let n = observations.len();
+    let recalled = observations.iter().filter(|o| o.recalled).count();
+    if n == 0 {
+        return Err("no reviews following a successful review to fit".to_string());
+    }
+    if recalled == 0 || recalled == n {
+        return Err(format!(
+            "all {} reviews were {}, so there's no curve to fit",
+            n,
+            if recalled == 0 {
+                "forgotten"
+            } else {
+                "recalled"
+            }
+        ));
+    }
+
+    // The log-likelihood is concave in the rate, so its slope crosses zero
+    // once.  Bisect for that in log space.
+    let slope = |rate: f64| -> f64 {
+        observations
+            .iter()
+            .map(|o| {
+                if o.recalled {
+                    -o.factor
+                } else {
+                    o.factor / (rate * o.factor).exp_m1()
+                }
+            })
+            .sum()
+    };
+    let (mut lo, mut hi) = (1.0e-9f64.ln(), 1.0e3f64.ln());
+    for _ in 0..200 {
+        let mid = (lo + hi) / 2.0;
+        if slope(mid.exp()) > 0.0 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let rate = ((lo + hi) / 2.0).exp();
+
+    let predict = |o: &Observation| (-rate * o.factor).exp();
+    let log_p = |p: f64, recalled: bool| if recalled { p.ln() } else { (1.0 - p).ln() };
+    let observed_retention = recalled as f64 / n as f64;
+    let information: f64 = observations
+        .iter()
+        .map(|o| {
+            let p = predict(o);
+            o.factor * o.factor * p / (1.0 - p)
+        })
+        .sum();
+
+    // Equal-count bins by interval factor.
+    let mut sorted = observations.to_vec();
+    sorted.sort_by(|a, b| a.factor.partial_cmp(&b.factor).unwrap());
+    let bin_count = CALIBRATION_BINS.min(n);
+    let bins: Vec<CalibrationBin> = (0..bin_count)
+        .map(|b| {
+            let chunk = &sorted[b * n / bin_count..(b + 1) * n / bin_count];
+            let count = chunk.len();
+            CalibrationBin {
+                min_factor: chunk[0].factor,
+                max_factor: chunk[count - 1].factor,
+                count,
+                observed: chunk.iter().filter(|o| o.recalled).count() as f64 / count as f64,
+                predicted: chunk.iter().map(&predict).sum::<f64>() / count as f64,
+            }
+        })
+        .collect();
+    let chi_squared = bins
+        .iter()
+        .map(|b| {
+            let expected = b.predicted * b.count as f64;
+            let variance = expected * (1.0 - b.predicted);
+            let diff = b.observed * b.count as f64 - expected;
+            diff * diff / variance
+        })
+        .sum();
+
+    Ok(RetentionFit {
+        rate,
+        rate_std_err: 1.0 / information.sqrt(),
+        observations: n,
+        observed_retention,
+        mean_factor: observations.iter().map(|o| o.factor).sum::<f64>() / n as f64,
+        log_likelihood: observations
+            .iter()
+            .map(|o| log_p(predict(o), o.recalled))
+            .sum(),
+        null_log_likelihood: observations
+            .iter()
+            .map(|o| log_p(observed_retention, o.recalled))
+            .sum(),
+        brier_score: observations
+            .iter()
+            .map(|o| {
+                let y = if o.recalled { 1.0 } else { 0.0 };
+                (y - predict(o)).powi(2)
+            })
+            .sum::<f64>()
+            / n as f64,
+        degrees_of_freedom: bins.len().saturating_sub(2),
+        bins,
+        chi_squared,
+    })
+}
+
+/// Which settings `calibrate` fits.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Fit {
+    Retention,
+    Times,
+    All,
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fit, String> {
+        match s {
+            "retention" => Ok(Fit::Retention),
+            "times" => Ok(Fit::Times),
+            "all" => Ok(Fit::All),
+            _ => Err(format!(
+                "unknown fit '{}' (expected retention, times or all)",
+                s
+            )),
+        }
+    }
+}
+
+impl Fit {
+    pub fn retention(&self) -> bool {
+        *self != Fit::Times
+    }
+
+    pub fn times(&self) -> bool {
+        *self != Fit::Retention
+    }
+}
+
+/// Seconds spent per card, fitted to a review history the way the engines
+/// charge them: every review costs `seconds_per_review_card`, a lapse
+/// costs `seconds_per_lapsed_card` on top of that, and a new card costs
+/// `seconds_per_new_card` for all its learning steps.  Each is `None` when
+/// the history has no examples.
+#[derive(Debug, Clone)]
+pub struct TimeFit {
+    /// All the learning steps of each new card, counting only cards whose
+    /// first answer is in the log.
+    pub new_card: Option<Summary>,
+    pub new_cards: usize,
+    /// Every answer to a due review, recalled or not.
+    pub review_card: Option<Summary>,
+    pub reviews: usize,
+    /// The relearning steps that followed each lapse.
+    pub lapsed_card: Option<Summary>,
+    pub lapses: usize,
+    /// Mean seconds by the interval the card was reviewed at, in the
+    /// doubling bins of `deck_state`.
+    pub by_interval: Vec<IntervalTimes>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IntervalTimes {
+    pub reviews: usize,
+    pub lapses: usize,
+    pub recalled_seconds: f64,
+    pub forgotten_seconds: f64,
+    pub relearning_seconds: f64,
+}
+
+/// Fits time costs from answer durations.  `reviews` must be ordered by
+/// card and then time.
+pub fn fit_times(reviews: &[Review]) -> Result<TimeFit, String> {
+    let seconds = |r: &Review| r.time as f64 / 1000.0;
+    let mut new_cards = Vec::new();
+    let mut review_cards = Vec::new();
+    let mut lapsed_cards = Vec::new();
+    let mut bins = vec![IntervalTimes::default(); deck_state::INTERVAL_BINS];
+
+    for (i, review) in reviews.iter().enumerate() {
+        let first = i == 0 || reviews[i - 1].card != review.card;
+        // The entries straight after this one for the same card and of a
+        // given kind.
+        let following = |kind: i64| {
+            reviews[i + 1..]
+                .iter()
+                .take_while(move |r| r.card == review.card && r.kind == kind)
+        };
+        if first && review.kind == LEARNING {
+            let steps: f64 = following(LEARNING).map(&seconds).sum();
+            new_cards.push(seconds(review) + steps);
+        }
+        if review.kind != REVIEW {
+            continue;
+        }
+        review_cards.push(seconds(review));
+        let bin = &mut bins[deck_state::interval_bin(review.last_interval as f32)];
+        bin.reviews += 1;
+        if review.recalled() {
+            bin.recalled_seconds += seconds(review);
+        } else {
+            let relearning: f64 = following(RELEARNING).map(&seconds).sum();
+            lapsed_cards.push(relearning);
+            bin.lapses += 1;
+            bin.forgotten_seconds += seconds(review);
+            bin.relearning_seconds += relearning;
+        }
+    }
+    if new_cards.is_empty() && review_cards.is_empty() {
+        return Err("no learning or review answers to time".to_string());
+    }
+
+    for bin in &mut bins {
+        let recalled = bin.reviews - bin.lapses;
+        bin.recalled_seconds /= recalled.max(1) as f64;
+        bin.forgotten_seconds /= bin.lapses.max(1) as f64;
+        bin.relearning_seconds /= bin.lapses.max(1) as f64;
+    }
+    let summary = |samples: &[f64]| {
+        if samples.is_empty() {
+            None
+        } else {
+            Some(summarize(samples))
+        }
+    };
+    Ok(TimeFit {
+        new_card: summary(&new_cards),
+        new_cards: new_cards.len(),
+        review_card: summary(&review_cards),
+        reviews: review_cards.len(),
+        lapsed_card: summary(&lapsed_cards),
+        lapses: lapsed_cards.len(),
+        by_interval: bins,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400_000;
+
+    fn review(card: i64, day: f64, ease: i64, kind: i64) -> Review {
+        Review {
+            id: (day * DAY as f64) as i64,
+            card,
+            ease,
+            interval: 0,
+            last_interval: 0,
+            factor: 2500,
+            time: 10_000,
+            kind,
+        }
+    }
+
+    /// `recalled` out of `n` reviews at each interval factor, with the
+    /// lapses spread evenly among them.
+    fn observed(factors: &[(f64, usize, usize)]) -> Vec<Observation> {
+        let mut observations = Vec::new();
+        for &(factor, recalled, n) in factors {
+            for i in 0..n {
+                observations.push(Observation {
+                    factor,
+                    recalled: (i + 1) * recalled / n > i * recalled / n,
+                });
+            }
+        }
+        observations
+    }
+
+    #[test]
+    fn times_each_kind_of_answer() {
+        let timed = |mut r: Review, seconds: i64, last_interval: i64| {
+            r.time = seconds * 1000;
+            r.last_interval = last_interval;
+            r
+        };
+        let mut reviews = vec![
+            // A new card with three learning steps.
+            timed(review(1, 0.0, 3, LEARNING), 20, 0),
+            timed(review(1, 0.01, 3, LEARNING), 10, 0),
+            timed(review(1, 0.02, 3, LEARNING), 5, 0),
+            timed(review(1, 1.0, 3, REVIEW), 6, 1),
+            // A lapse and its relearning steps.
+            timed(review(1, 4.0, 1, REVIEW), 12, 3),
+            timed(review(1, 4.01, 3, RELEARNING), 4, 0),
+            timed(review(1, 4.02, 3, RELEARNING), 3, 0),
+            // A card first seen at a review, so its later learning step
+            // isn't timed as a new card.
+            timed(review(2, 0.5, 3, LEARNING), 50, 0),
+            timed(review(2, 0.0, 3, REVIEW), 8, 2),
+        ];
+        reviews.sort_by_key(|r| (r.card, r.id));
+        let fit = fit_times(&reviews).unwrap();
+        assert_eq!(fit.new_cards, 1);
+        assert_eq!(fit.new_card.unwrap().mean, 35.0);
+        assert_eq!(fit.reviews, 3);
+        assert!((fit.review_card.unwrap().mean - 26.0 / 3.0).abs() < 1e-9);
+        assert_eq!(fit.lapses, 1);
+        assert_eq!(fit.lapsed_card.unwrap().mean, 7.0);
+
+        let bin = fit.by_interval[deck_state::interval_bin(3.0)];
+        assert_eq!((bin.reviews, bin.lapses), (2, 1));
+        assert_eq!(bin.recalled_seconds, 8.0);
+        assert_eq!(bin.forgotten_seconds, 12.0);
+        assert_eq!(bin.relearning_seconds, 7.0);
+    }
+
+    #[test]
+    fn timing_needs_answers() {
+        assert!(fit_times(&[]).is_err());
+        assert!(fit_times(&[review(1, 0.0, 3, RELEARNING)]).is_err());
+    }
+
+    #[test]
+    fn observes_stretched_intervals() {
+        let reviews = vec![
+            review(1, 0.0, 3, LEARNING),
+            review(1, 1.0, 3, REVIEW),
+            review(1, 3.0, 3, REVIEW),
+            // Two days on from 3, over the 2 before: a factor of 3.
+            review(1, 9.0, 1, REVIEW),
+            review(1, 9.01, 3, RELEARNING),
+            // Follows a lapse, so isn't observed.
+            review(1, 12.0, 3, REVIEW),
+            // A different card's first reviews.
+            review(2, 12.5, 3, REVIEW),
+            review(2, 13.0, 3, REVIEW),
+        ];
+        let observations = observations(&reviews);
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[0].factor, 2.0);
+        assert!(observations[0].recalled);
+        assert_eq!(observations[1].factor, 3.0);
+        assert!(!observations[1].recalled);
+    }
+
+    #[test]
+    fn fits_the_observed_retention() {
+        // At a single factor, the best fit predicts exactly what was seen.
+        let fit = fit_retention(&observed(&[(2.5, 90, 100)])).unwrap();
+        assert!((fit.retention(2.5) - 0.9).abs() < 1e-9);
+        assert!((fit.log_likelihood - fit.null_log_likelihood).abs() < 1e-9);
+        let (lo, hi) = fit.retention_ci(2.5);
+        assert!(lo < 0.9 && hi > 0.9);
+
+        // Data that follows the curve is fitted at its rate.
+        let rate = -(0.9f64.ln()) / 2.5;
+        let fit = fit_retention(&observed(&[
+            (1.0, ((-rate).exp() * 1e4).round() as usize, 10_000),
+            (5.0, ((-rate * 5.0).exp() * 1e4).round() as usize, 10_000),
+        ]))
+        .unwrap();
+        assert!((fit.rate / rate - 1.0).abs() < 1e-3);
+        assert_eq!(fit.bins.len(), 10);
+        assert!(fit.chi_squared < 1.0);
+    }
+
+    #[test]
+    fn fits_need_both_outcomes() {
+        assert!(fit_retention(&[]).is_err());
+        assert!(fit_retention(&observed(&[(2.0, 5, 5)])).is_err());
+        assert!(fit_retention(&observed(&[(2.0, 0, 5)])).is_err());
+    }
+}
@@ -0,0 +1,1063 @@
+//! Interval factor vs measured retention charts.
+
+use cli;
+use colormap::Colormap;
+use font;
+use image::{Image, BLACK, RED, WHITE};
+use params::{Engine, Metrics, Params};
+use ridge;
+use scale;
+use stats::Stat;
+use svg::{self, Svg};
+use sweep;
+
+/// The interval factor vs measured retention grid that `chart` and `sweep`
+/// cover.  Interval factor runs along the y axis, measured retention along x.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub interval_range: (f32, f32),
+    pub interval_cells: u32,
+    pub retention_range: (f32, f32),
+    pub retention_cells: u32,
+}
+
+impl Grid {
+    pub fn from_args(args: &mut cli::Args, default_interval_cells: u32) -> Result<Grid, String> {
+        let grid = Grid {
+            interval_range: args.get_range("interval-range", (2.0, 10.0))?,
+            interval_cells: args.get("interval-cells", default_interval_cells)?,
+            retention_range: args.get_range("retention-range", (0.000001, 1.0))?,
+            retention_cells: args.get("retention-cells", 101)?,
+        };
+        if grid.interval_cells < 2 || grid.retention_cells < 2 {
+            return Err("grids need at least 2 cells along each axis".to_string());
+        }
+        Ok(grid)
+    }
+
+    pub fn interval_step(&self) -> f32 {
+        (self.interval_range.1 - self.interval_range.0) / (self.interval_cells - 1) as f32
+    }
+
+    pub fn retention_step(&self) -> f32 {
+        (self.retention_range.1 - self.retention_range.0) / (self.retention_cells - 1) as f32
+    }
+
+    pub fn interval_factor(&self, y: usize) -> f32 {
+        self.interval_range.0 + (self.interval_step() * y as f32)
+    }
+
+    pub fn retention(&self, x: usize) -> f32 {
+        self.retention_range.0 + (self.retention_step() * x as f32)
+    }
+
+    /// The grid covered by saved sweep axes, which must be measured
+    /// retention then interval factor, each evenly spaced.
+    pub fn from_axes(axes: &[sweep::Axis]) -> Result<Grid, String> {
+        let names: Vec<&str> = axes.iter().map(|a| a.name.as_str()).collect();
+        if names != ["measured_retention", "interval_factor"] {
+            return Err(format!(
+                "charts need a sweep over measured_retention and interval_factor, not {}",
+                names.join(", ")
+            ));
+        }
+        for axis in axes {
+            let n = axis.values.len();
+            if n < 2 {
+                return Err("grids need at least 2 cells along each axis".to_string());
+            }
+            let step = (axis.values[n - 1] - axis.values[0]) / (n - 1) as f32;
+            let even = axis.values.iter().enumerate().all(|(i, v)| {
+                (v - (axis.values[0] + step * i as f32)).abs() <= step.abs() * 1.0e-3
+            });
+            if !even {
+                return Err(format!("axis '{}' isn't evenly spaced", axis.name));
+            }
+        }
+        let (retention, interval) = (&axes[0].values, &axes[1].values);
+        Ok(Grid {
+            interval_range: (interval[0], interval[interval.len() - 1]),
+            interval_cells: interval.len() as u32,
+            retention_range: (retention[0], retention[retention.len() - 1]),
+            retention_cells: retention.len() as u32,
+        })
+    }
+
+    /// Crops a chart over this grid to the cells within the given ranges.
+    pub fn crop(
+        &self,
+        chart: &[f32],
+        interval_range: (f32, f32),
+        retention_range: (f32, f32),
+    ) -> Result<(Vec<f32>, Grid), String> {
+        let inside =
+            |v: f32, r: (f32, f32)| v >= r.0.min(r.1) - 1.0e-6 && v <= r.0.max(r.1) + 1.0e-6;
+        let ys: Vec<usize> = (0..self.interval_cells as usize)
+            .filter(|&y| inside(self.interval_factor(y), interval_range))
+            .collect();
+        let xs: Vec<usize> = (0..self.retention_cells as usize)
+            .filter(|&x| inside(self.retention(x), retention_range))
+            .collect();
+        if ys.len() < 2 || xs.len() < 2 {
+            return Err("the crop leaves fewer than 2 cells along an axis".to_string());
+        }
+
+        let width = self.retention_cells as usize;
+        let mut cropped = Vec::with_capacity(ys.len() * xs.len());
+        for &y in &ys {
+            for &x in &xs {
+                cropped.push(chart[y * width + x]);
+            }
+        }
+        let grid = Grid {
+            interval_range: (
+                self.interval_factor(ys[0]),
+                self.interval_factor(ys[ys.len() - 1]),
+            ),
+            interval_cells: ys.len() as u32,
+            retention_range: (self.retention(xs[0]), self.retention(xs[xs.len() - 1])),
+            retention_cells: xs.len() as u32,
+        };
+        Ok((cropped, grid))
+    }
+
+    /// The grid as sweep axes: measured retention, then interval factor.
+    pub fn axes(&self) -> [sweep::Axis; 2] {
+        [
+            sweep::Axis::linear(
+                "measured_retention",
+                self.retention_range.0,
+                self.retention_range.1,
+                self.retention_cells,
+            ),
+            sweep::Axis::linear(
+                "interval_factor",
+                self.interval_range.0,
+                self.interval_range.1,
+                self.interval_cells,
+            ),
+        ]
+    }
+}
+
+/// Pulls one metric out of sweep results over `grid.axes()`, in row-major
+/// chart order (`y * width + x`).  Analytical results are blurred along the
+/// interval axis.
+pub fn chart_values(results: &[Metrics], grid: &Grid, metric: &str, engine: Engine) -> Vec<f32> {
+    let height = grid.interval_cells as usize;
+    let width = grid.retention_cells as usize;
+
+    // The sweep varies its last axis fastest, i.e. goes column by column.
+    let mut chart = vec![0.0f32; height * width];
+    for x in 0..width {
+        for y in 0..height {
+            chart[y * width + x] = results[x * height + y].get(metric).unwrap();
+        }
+    }
+
+    if engine == Engine::Analytical {
+        blur_intervals(&chart, grid)
+    } else {
+        chart
+    }
+}
+
+/// Simulates the grid and returns `metric` in row-major chart order.
+pub fn compute_chart(params: &Params, grid: &Grid, metric: &str) -> Vec<f32> {
+    chart_values(
+        &sweep::run(params, &grid.axes()),
+        grid,
+        metric,
+        params.engine,
+    )
+}
+
+/// Blurs vertically to approximate interval variance, which the analytical
+/// sim doesn't model.
+pub fn blur_intervals(chart: &[f32], grid: &Grid) -> Vec<f32> {
+    let interval_range = grid.interval_range;
+    let height = grid.interval_cells as usize;
+    let interval_step = grid.interval_step();
+    let width = grid.retention_cells as usize;
+
+    let mut chart2 = vec![0.0f32; height * width];
+    for x in 0..width {
+        for y in 0..height {
+            let interval = interval_range.0 + (y as f32 * interval_step);
+            let blur_size = ((interval * 0.1) / interval_step) as usize;
+            let start = y - blur_size.min(y);
+            let end = (y + blur_size).min(height - 1);
+
+            chart2[y * width + x] = {
+                let mut val = 0.0;
+                for i in start..(end + 1) {
+                    val += chart[i * width + x];
+                }
+                val += chart[y * width + x] * ((blur_size * 2 + 1) - (end - start + 1)) as f32;
+                val / (blur_size * 2 + 1) as f32
+            };
+        }
+    }
+
+    chart2
+}
+
+/// Settings for how a chart is drawn.
+pub struct ChartOptions {
+    /// How values map to colors.
+    pub scaling: scale::Scaling,
+    /// Draw the optimal-interval ridge over the chart.
+    pub ridge: bool,
+    /// Draw iso-contours at these fractions of each column's maximum.
+    pub contours: Vec<f32>,
+    /// Mark the optimum of each column and of the whole chart.
+    pub optima: bool,
+    /// The metric shown, and which statistic of it for replicated sweeps.
+    pub metric: String,
+    pub stat: Stat,
+    pub colormap: Colormap,
+    pub title: String,
+    /// Size in pixels of each grid cell.
+    pub magnification: usize,
+}
+
+impl ChartOptions {
+    pub fn from_args(args: &mut cli::Args, params: &Params) -> Result<ChartOptions, String> {
+        let metric = args.get("metric", "cards_learned_per_hour".to_string())?;
+        if Metrics::default().get(&metric).is_none() {
+            return Err(format!("unknown metric '{}'", metric));
+        }
+        let stat = args.get("stat", Stat::Mean)?;
+        let default_title = format!("{} ({} engine)", stat.label(&metric), params.engine.name());
+        // --normalize-slices predates --scale and picks between its two
+        // original behaviors.  The spread of a metric is best shown as is.
+        let default_mode = if args.get("normalize-slices", stat == Stat::Mean)? {
+            scale::Mode::Columns
+        } else {
+            scale::Mode::Absolute
+        };
+        let scaling = scale::Scaling {
+            mode: args.get("scale", default_mode)?,
+            range: match args.take("range") {
+                Some(r) => Some(cli::parse_range(&r).map_err(|e| format!("--range: {}", e))?),
+                None => None,
+            },
+            clip: args.get("clip", 0.0f32)? / 100.0,
+            log: args.get("log", false)?,
+        };
+        if scaling.clip < 0.0 || scaling.clip >= 0.5 {
+            return Err("--clip must be at least 0 and below 50 percent".to_string());
+        }
+        if let (true, Some(range)) = (scaling.log, scaling.range) {
+            if range.0 <= 0.0 || range.1 <= 0.0 {
+                return Err("--log needs a positive --range".to_string());
+            }
+        }
+        let options = ChartOptions {
+            scaling,
+            ridge: args.get("ridge", false)?,
+            contours: match args.take("contours") {
+                Some(list) => parse_levels(&list)?,
+                None => Vec::new(),
+            },
+            optima: args.get("optima", false)?,
+            metric,
+            stat,
+            colormap: args.get("colormap", Colormap::Viridis)?,
+            title: args.get("title", default_title)?,
+            magnification: args.get("magnification", 10)?,
+        };
+        if options.magnification == 0 {
+            return Err("--magnification must be at least 1".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Parses a comma-separated list of contour levels, as fractions or as
+/// percentages ending in `%`.
+fn parse_levels(list: &str) -> Result<Vec<f32>, String> {
+    list.split(',')
+        .map(|l| {
+            let l = l.trim();
+            let (number, scale) = match l.strip_suffix('%') {
+                Some(percent) => (percent, 100.0),
+                None => (l, 1.0),
+            };
+            match number.trim().parse::<f32>() {
+                Ok(v) if (0.0..=scale).contains(&v) => Ok(v / scale),
+                _ => Err(format!(
+                    "invalid contour level '{}' (expected a fraction from 0 to 1, \
+                     or a percentage like 95%)",
+                    l
+                )),
+            }
+        })
+        .collect()
+}
+
+// Chart layout, in pixels.
+const TEXT_SCALE: usize = 2;
+const TITLE_SCALE: usize = 3;
+const TICK_LENGTH: usize = 6;
+const MARGIN_LEFT: usize = 100;
+const MARGIN_RIGHT: usize = 150;
+const MARGIN_TOP: usize = 50;
+const MARGIN_BOTTOM: usize = 70;
+const COLOR_BAR_GAP: usize = 20;
+const COLOR_BAR_WIDTH: usize = 20;
+
+/// Writes an already-computed chart as a labeled image, with whichever
+/// overlays `options` asks for.  Paths ending in `.svg` are written as
+/// SVG, anything else as PNG.
+pub fn render_chart(
+    path: &str,
+    chart: &[f32],
+    grid: &Grid,
+    options: &ChartOptions,
+) -> Result<(), String> {
+    if path.to_lowercase().ends_with(".svg") {
+        render_chart_svg(path, chart, grid, options)
+    } else {
+        render_chart_png(path, chart, grid, options)
+    }
+}
+
+const X_LABEL: &str = "Measured retention";
+const Y_LABEL: &str = "Interval factor";
+
+/// The parts of a chart that don't depend on the output format: where
+/// things go in grid coordinates (x counting retention cells, y counting
+/// interval cells), and ticks as fractions of the plot's width or height.
+struct Layout {
+    width: usize,
+    height: usize,
+    plot_width: usize,
+    plot_height: usize,
+    /// Each cell's position on the color bar, row-major.
+    colors: Vec<f32>,
+    color_scale: scale::ColorScale,
+    contours: Vec<((f32, f32), (f32, f32))>,
+    /// Each column's optimum, skipping columns without one.
+    optima: Vec<(f32, f32)>,
+    /// The best of `optima`.
+    global: Option<(f32, f32)>,
+    x_ticks: Vec<(f32, String)>,
+    y_ticks: Vec<(f32, String)>,
+    bar_ticks: Vec<(f32, String)>,
+    bar_contours: Vec<f32>,
+}
+
+impl Layout {
+    fn new(chart: &[f32], grid: &Grid, options: &ChartOptions) -> Layout {
+        let height = grid.interval_cells as usize;
+        let width = grid.retention_cells as usize;
+        // The plot area is the chart enlarged by the magnification factor.
+        let plot_width = (width - 1) * options.magnification;
+        let plot_height = (height - 1) * options.magnification;
+
+        let (values, color_scale) = scale::apply(
+            chart,
+            grid,
+            &options.scaling,
+            &options.stat.label(&options.metric),
+        );
+        let colors = values.iter().map(|&v| color_scale.position(v)).collect();
+
+        let relative = normalize_columns(chart, grid);
+        let contours = options
+            .contours
+            .iter()
+            .flat_map(|&level| contour_segments(&relative, width, height, level))
+            .collect();
+        // Where the color bar is relative to the column maximum too, the
+        // contour levels can be shown on it.
+        let bar_contours = options
+            .contours
+            .iter()
+            .filter_map(|&level| color_scale.contour_position(level))
+            .collect();
+
+        let mut optima = Vec::new();
+        let mut global = None;
+        let mut best = 0.0;
+        for (x, p) in ridge::extract(chart, grid, 0.0).iter().enumerate() {
+            if p.value.is_nan() {
+                continue;
+            }
+            let point = (
+                x as f32,
+                (p.interval_factor - grid.interval_range.0) / grid.interval_step(),
+            );
+            if global.is_none() || p.value > best {
+                global = Some(point);
+                best = p.value;
+            }
+            optima.push(point);
+        }
+
+        let axis_ticks = |range: (f32, f32), target: usize| -> Vec<(f32, String)> {
+            let (ticks, step) = nice_ticks(range.0, range.1, target);
+            ticks
+                .into_iter()
+                .map(|t| ((t - range.0) / (range.1 - range.0), format_tick(t, step)))
+                .collect()
+        };
+
+        Layout {
+            width,
+            height,
+            plot_width,
+            plot_height,
+            colors,
+            contours,
+            optima,
+            global,
+            x_ticks: axis_ticks(grid.retention_range, (plot_width / 90).max(2)),
+            y_ticks: axis_ticks(grid.interval_range, (plot_height / 50).max(2)),
+            bar_ticks: color_scale.ticks((plot_height / 80).max(2)),
+            bar_contours,
+            color_scale,
+        }
+    }
+}
+
+fn render_chart_png(
+    path: &str,
+    chart: &[f32],
+    grid: &Grid,
+    options: &ChartOptions,
+) -> Result<(), String> {
+    let layout = Layout::new(chart, grid, options);
+    let (width, height) = (layout.width, layout.height);
+    let (plot_width, plot_height) = (layout.plot_width, layout.plot_height);
+
+    // Lay out the canvas around the plot area, widening the left margin if
+    // the y axis labels need more room.
+    let text_h = font::text_height(TEXT_SCALE);
+    let widest_label = layout
+        .y_ticks
+        .iter()
+        .map(|(_, label)| font::text_width(label, TEXT_SCALE))
+        .max()
+        .unwrap_or(0);
+    let plot_left = MARGIN_LEFT.max(TICK_LENGTH + 6 + widest_label + text_h + 20);
+    let plot_top = MARGIN_TOP;
+    let plot_bottom = plot_top + plot_height; // One past the last row.
+    let title_width = font::text_width(&options.title, TITLE_SCALE);
+    let mut image = Image::new(
+        (plot_left + plot_width + MARGIN_RIGHT).max(title_width + 20),
+        MARGIN_TOP + plot_height + MARGIN_BOTTOM,
+        WHITE,
+    );
+
+    // The heatmap, bilinearly interpolated.  Interval factor increases
+    // upwards.
+    let chart = &layout.colors;
+    let width_map_fac = (width - 1) as f64 / (plot_width - 1).max(1) as f64;
+    let height_map_fac = (height - 1) as f64 / (plot_height - 1).max(1) as f64;
+    for x in 0..plot_width {
+        for y in 0..plot_height {
+            let val = {
+                let small_x = x as f64 * width_map_fac;
+                let small_y = y as f64 * height_map_fac;
+                let sx1 = small_x as usize;
+                let sy1 = small_y as usize;
+                let sx2 = (sx1 + 1).min(width - 1);
+                let sy2 = (sy1 + 1).min(height - 1);
+                let alpha_x = (small_x - small_x.floor()) as f32;
+                let alpha_y = (small_y - small_y.floor()) as f32;
+
+                let left = {
+                    let val1 = chart[sy1 * width + sx1];
+                    let val2 = chart[sy2 * width + sx1];
+                    (val1 * (1.0 - alpha_y)) + (val2 * alpha_y)
+                };
+
+                let right = {
+                    let val1 = chart[sy1 * width + sx2];
+                    let val2 = chart[sy2 * width + sx2];
+                    (val1 * (1.0 - alpha_y)) + (val2 * alpha_y)
+                };
+
+                (left * (1.0 - alpha_x)) + (right * alpha_x)
+            };
+            image.set(
+                (plot_left + x) as isize,
+                (plot_bottom - 1 - y) as isize,
+                options.colormap.sample(val),
+            );
+        }
+    }
+
+    // Maps grid coordinates to canvas pixels, and fractions of the plot's
+    // height to rows.
+    let to_pixel = |(gx, gy): (f32, f32)| -> (isize, isize) {
+        (
+            plot_left as isize + (gx as f64 / width_map_fac).round() as isize,
+            plot_bottom as isize - 1 - (gy as f64 / height_map_fac).round() as isize,
+        )
+    };
+    let to_row =
+        |frac: f32| (plot_bottom as f32 - 1.0 - frac * (plot_height - 1) as f32).round() as isize;
+
+    for &(a, b) in &layout.contours {
+        image.line(to_pixel(a), to_pixel(b), 1, BLACK);
+    }
+
+    if options.ridge {
+        // Draw the ridge as a red line through each column's optimum.
+        let points: Vec<(isize, isize)> = layout.optima.iter().map(|&p| to_pixel(p)).collect();
+        for pair in points.windows(2) {
+            image.line(pair[0], pair[1], 2, RED);
+        }
+    }
+
+    if options.optima {
+        // A small dot on each column's optimum, and a ring around the
+        // global one.
+        for &p in &layout.optima {
+            let (px, py) = to_pixel(p);
+            image.fill_rect(px - 2, py - 2, 5, 5, BLACK);
+            image.fill_rect(px - 1, py - 1, 3, 3, WHITE);
+        }
+        if let Some(p) = layout.global {
+            let (px, py) = to_pixel(p);
+            for r in &[7.0f32, 6.0, 5.0] {
+                let color = if *r == 6.0 { WHITE } else { BLACK };
+                for i in 0..64 {
+                    let angle = i as f32 / 64.0 * 2.0 * ::std::f32::consts::PI;
+                    image.set(
+                        px + (angle.cos() * r).round() as isize,
+                        py + (angle.sin() * r).round() as isize,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+
+    image.outline_rect(
+        plot_left as isize - 1,
+        plot_top as isize - 1,
+        plot_width + 2,
+        plot_height + 2,
+        BLACK,
+    );
+
+    // X axis: measured retention.
+    let text_h = text_h as isize;
+    for (frac, label) in &layout.x_ticks {
+        let x = (plot_left as f32 + frac * (plot_width - 1) as f32).round() as isize;
+        image.fill_rect(x, plot_bottom as isize, 1, TICK_LENGTH, BLACK);
+        image.text(
+            x - font::text_width(label, TEXT_SCALE) as isize / 2,
+            (plot_bottom + TICK_LENGTH + 4) as isize,
+            label,
+            TEXT_SCALE,
+            BLACK,
+        );
+    }
+    image.text(
+        (plot_left + plot_width / 2) as isize - font::text_width(X_LABEL, TEXT_SCALE) as isize / 2,
+        (plot_bottom + TICK_LENGTH + 4) as isize + text_h + 12,
+        X_LABEL,
+        TEXT_SCALE,
+        BLACK,
+    );
+
+    // Y axis: interval factor.
+    for &(frac, ref label) in &layout.y_ticks {
+        let y = to_row(frac);
+        image.fill_rect(
+            (plot_left - TICK_LENGTH) as isize - 1,
+            y,
+            TICK_LENGTH,
+            1,
+            BLACK,
+        );
+        let label_width = font::text_width(label, TEXT_SCALE);
+        image.text(
+            (plot_left - TICK_LENGTH - 6 - label_width) as isize,
+            y - text_h / 2,
+            label,
+            TEXT_SCALE,
+            BLACK,
+        );
+    }
+    let scale = vertical_label_scale(Y_LABEL, plot_height);
+    image.text_vertical(
+        (plot_left - TICK_LENGTH - 6 - widest_label) as isize - text_h - 10,
+        (plot_top + plot_height / 2) as isize + font::text_width(Y_LABEL, scale) as isize / 2,
+        Y_LABEL,
+        scale,
+        BLACK,
+    );
+
+    // Title, centered over the plot unless it's too wide to fit there.
+    let title_center = if title_width + 20 > plot_width {
+        image.width / 2
+    } else {
+        plot_left + plot_width / 2
+    };
+    image.text(
+        title_center as isize - title_width as isize / 2,
+        (MARGIN_TOP - font::text_height(TITLE_SCALE)) as isize / 2,
+        &options.title,
+        TITLE_SCALE,
+        BLACK,
+    );
+
+    // Color bar.
+    let bar_left = plot_left + plot_width + COLOR_BAR_GAP;
+    for y in 0..plot_height {
+        let t = y as f32 / (plot_height - 1).max(1) as f32;
+        image.fill_rect(
+            bar_left as isize,
+            (plot_bottom - 1 - y) as isize,
+            COLOR_BAR_WIDTH,
+            1,
+            options.colormap.sample(t),
+        );
+    }
+    image.outline_rect(
+        bar_left as isize - 1,
+        plot_top as isize - 1,
+        COLOR_BAR_WIDTH + 2,
+        plot_height + 2,
+        BLACK,
+    );
+    for &frac in &layout.bar_contours {
+        image.fill_rect(bar_left as isize, to_row(frac), COLOR_BAR_WIDTH, 1, BLACK);
+    }
+    let bar_right = (bar_left + COLOR_BAR_WIDTH + 1) as isize;
+    let mut widest_label = 0;
+    for &(frac, ref label) in &layout.bar_ticks {
+        let y = to_row(frac);
+        image.fill_rect(bar_right, y, TICK_LENGTH, 1, BLACK);
+        widest_label = widest_label.max(font::text_width(label, TEXT_SCALE));
+        image.text(
+            bar_right + TICK_LENGTH as isize + 4,
+            y - text_h / 2,
+            label,
+            TEXT_SCALE,
+            BLACK,
+        );
+    }
+    let bar_label = &layout.color_scale.label;
+    let scale = vertical_label_scale(bar_label, plot_height);
+    image.text_vertical(
+        bar_right + (TICK_LENGTH + 4 + widest_label + 10) as isize,
+        (plot_top + plot_height / 2) as isize + font::text_width(bar_label, scale) as isize / 2,
+        bar_label,
+        scale,
+        BLACK,
+    );
+
+    image
+        .write_png(path)
+        .map_err(|e| format!("couldn't write '{}': {}", path, e))
+}
+
+fn render_chart_svg(
+    path: &str,
+    chart: &[f32],
+    grid: &Grid,
+    options: &ChartOptions,
+) -> Result<(), String> {
+    let layout = Layout::new(chart, grid, options);
+    let (width, height) = (layout.width, layout.height);
+
+    let cell = options.magnification as f32;
+    let plot_width = layout.plot_width as f32;
+    let plot_height = layout.plot_height as f32;
+    let plot_left = MARGIN_LEFT as f32;
+    let plot_top = MARGIN_TOP as f32;
+    let plot_bottom = plot_top + plot_height;
+    // Viewers pick the font, so this is only an estimate of the width.
+    let title_width = options.title.chars().count() as f32 * 11.0;
+    let mut svg = Svg::new(
+        (MARGIN_LEFT as f32 + plot_width + MARGIN_RIGHT as f32).max(title_width + 20.0),
+        MARGIN_TOP as f32 + plot_height + MARGIN_BOTTOM as f32,
+    );
+    let to_point = |(gx, gy): (f32, f32)| (plot_left + gx * cell, plot_bottom - gy * cell);
+
+    // The heatmap: one square per grid point, clipped to the plot area.
+    svg.raw(&format!(
+        "<clipPath id=\"plot\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath>",
+        plot_left, plot_top, plot_width, plot_height
+    ));
+    svg.raw("<g clip-path=\"url(#plot)\" shape-rendering=\"crispEdges\">");
+    for y in 0..height {
+        for x in 0..width {
+            let (cx, cy) = to_point((x as f32, y as f32));
+            svg.rect(
+                cx - cell * 0.5,
+                cy - cell * 0.5,
+                cell,
+                cell,
+                options.colormap.sample(layout.colors[y * width + x]),
+            );
+        }
+    }
+    svg.raw("</g>");
+
+    for &(a, b) in &layout.contours {
+        svg.line(to_point(a), to_point(b), 1.0, BLACK);
+    }
+    if options.ridge {
+        let points: Vec<(f32, f32)> = layout.optima.iter().map(|&p| to_point(p)).collect();
+        svg.polyline(&points, 2.0, RED);
+    }
+    if options.optima {
+        for &p in &layout.optima {
+            svg.circle(to_point(p), 2.0, Some(WHITE), BLACK);
+        }
+        if let Some(p) = layout.global {
+            svg.circle(to_point(p), 6.0, None, BLACK);
+        }
+    }
+    svg.outline_rect(plot_left, plot_top, plot_width, plot_height, BLACK);
+
+    // Axes.
+    for (frac, label) in &layout.x_ticks {
+        let x = plot_left + frac * plot_width;
+        svg.line(
+            (x, plot_bottom),
+            (x, plot_bottom + TICK_LENGTH as f32),
+            1.0,
+            BLACK,
+        );
+        svg.text(x, plot_bottom + 24.0, label, 14.0, "middle");
+    }
+    svg.text(
+        plot_left + plot_width / 2.0,
+        plot_bottom + 52.0,
+        X_LABEL,
+        16.0,
+        "middle",
+    );
+    for (frac, label) in &layout.y_ticks {
+        let y = plot_bottom - frac * plot_height;
+        svg.line(
+            (plot_left - TICK_LENGTH as f32, y),
+            (plot_left, y),
+            1.0,
+            BLACK,
+        );
+        svg.text(plot_left - 10.0, y + 5.0, label, 14.0, "end");
+    }
+    svg.text_vertical(30.0, plot_top + plot_height / 2.0, Y_LABEL, 16.0);
+    let title_center = if title_width + 20.0 > plot_width {
+        svg.width() / 2.0
+    } else {
+        plot_left + plot_width / 2.0
+    };
+    svg.text(
+        title_center,
+        plot_top - 18.0,
+        &options.title,
+        20.0,
+        "middle",
+    );
+
+    // Color bar, as a vertical gradient.
+    let bar_left = plot_left + plot_width + COLOR_BAR_GAP as f32;
+    let mut stops = String::new();
+    for i in 0..17 {
+        let t = i as f32 / 16.0;
+        stops.push_str(&format!(
+            "<stop offset=\"{}\" stop-color=\"{}\"/>",
+            t,
+            svg::color(options.colormap.sample(t))
+        ));
+    }
+    svg.raw(&format!(
+        "<linearGradient id=\"bar\" x1=\"0\" y1=\"1\" x2=\"0\" y2=\"0\">{}</linearGradient>",
+        stops
+    ));
+    svg.raw(&format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"url(#bar)\"/>",
+        bar_left, plot_top, COLOR_BAR_WIDTH, plot_height
+    ));
+    svg.outline_rect(
+        bar_left,
+        plot_top,
+        COLOR_BAR_WIDTH as f32,
+        plot_height,
+        BLACK,
+    );
+    for &frac in &layout.bar_contours {
+        let y = plot_bottom - frac * plot_height;
+        svg.line(
+            (bar_left, y),
+            (bar_left + COLOR_BAR_WIDTH as f32, y),
+            1.0,
+            BLACK,
+        );
+    }
+    let bar_right = bar_left + COLOR_BAR_WIDTH as f32;
+    for (frac, label) in &layout.bar_ticks {
+        let y = plot_bottom - frac * plot_height;
+        svg.line(
+            (bar_right, y),
+            (bar_right + TICK_LENGTH as f32, y),
+            1.0,
+            BLACK,
+        );
+        svg.text(bar_right + 10.0, y + 5.0, label, 14.0, "start");
+    }
+    svg.text_vertical(
+        bar_right + 80.0,
+        plot_top + plot_height / 2.0,
+        &layout.color_scale.label,
+        16.0,
+    );
+
+    svg.write(path)
+        .map_err(|e| format!("couldn't write '{}': {}", path, e))
+}
+
+/// Each column divided by its maximum, ignoring NaN cells.
+fn normalize_columns(chart: &[f32], grid: &Grid) -> Vec<f32> {
+    let height = grid.interval_cells as usize;
+    let width = grid.retention_cells as usize;
+    let mut relative = chart.to_vec();
+    for x in 0..width {
+        let max = (0..height)
+            .map(|y| chart[y * width + x])
+            .fold(0.0, |acc, v| if acc > v || v.is_nan() { acc } else { v });
+        for y in 0..height {
+            relative[y * width + x] /= max;
+        }
+    }
+    relative
+}
+
+/// Line segments, in grid coordinates, where `values` (row-major, `y *
+/// width + x`) crosses `level`.  Uses marching squares with linear
+/// interpolation along cell edges.
+pub fn contour_segments(
+    values: &[f32],
+    width: usize,
+    height: usize,
+    level: f32,
+) -> Vec<((f32, f32), (f32, f32))> {
+    let mut segments = Vec::new();
+    for y in 0..(height - 1) {
+        for x in 0..(width - 1) {
+            // Corners in counter-clockwise order, starting bottom-left.
+            let corners = [
+                (x as f32, y as f32, values[y * width + x]),
+                ((x + 1) as f32, y as f32, values[y * width + x + 1]),
+                (
+                    (x + 1) as f32,
+                    (y + 1) as f32,
+                    values[(y + 1) * width + x + 1],
+                ),
+                (x as f32, (y + 1) as f32, values[(y + 1) * width + x]),
+            ];
+            if corners.iter().any(|c| c.2.is_nan()) {
+                continue;
+            }
+
+            // Where the level crosses each of the four edges.
+            let mut crossings = Vec::with_capacity(4);
+            for i in 0..4 {
+                let (x1, y1, v1) = corners[i];
+                let (x2, y2, v2) = corners[(i + 1) % 4];
+                if (v1 < level) != (v2 < level) {
+                    let t = (level - v1) / (v2 - v1);
+                    crossings.push((x1 + (x2 - x1) * t, y1 + (y2 - y1) * t));
+                }
+            }
+
+            // Two crossings make one segment; four (a saddle) make two.
+            for pair in crossings.chunks(2) {
+                if pair.len() == 2 {
+                    segments.push((pair[0], pair[1]));
+                }
+            }
+        }
+    }
+    segments
+}
+
+/// Text scale for a vertical axis label, shrunk if it would overhang a
+/// short plot.
+fn vertical_label_scale(label: &str, plot_height: usize) -> usize {
+    if font::text_width(label, TEXT_SCALE) > plot_height {
+        1
+    } else {
+        TEXT_SCALE
+    }
+}
+
+/// "Nice" tick positions covering `[min, max]`, aiming for about
+/// `target` ticks.  Also returns the tick spacing, which determines how
+/// many decimals to print.
+pub fn nice_ticks(min: f32, max: f32, target: usize) -> (Vec<f32>, f32) {
+    let range = (max - min).abs();
+    if range <= 0.0 || !range.is_finite() {
+        return (vec![min], 1.0);
+    }
+    let rough = range / target.max(1) as f32;
+    let magnitude = 10.0f32.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 2.5, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&s| s >= rough)
+        .unwrap_or(10.0 * magnitude);
+
+    let lo = min.min(max);
+    let hi = min.max(max);
+    let mut ticks = Vec::new();
+    let mut i = (lo / step).ceil();
+    while i * step <= hi + step * 1.0e-4 {
+        ticks.push(i * step);
+        i += 1.0;
+    }
+    (ticks, step)
+}
+
+/// Formats a tick value with just enough decimals for the tick spacing.
+pub fn format_tick(value: f32, step: f32) -> String {
+    // Avoid printing "-0" for a zero that came out slightly negative.
+    let value = if value.abs() < step * 1.0e-3 {
+        0.0
+    } else {
+        value
+    };
+    let mut decimals = (-step.log10().floor()).max(0.0) as usize;
+    if (step / 10.0f32.powi(-(decimals as i32)) - 2.5).abs() < 1.0e-3 {
+        decimals += 1;
+    }
+    format!("{:.*}", decimals, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_round_numbers() {
+        assert_eq!(
+            nice_ticks(2.0, 10.0, 4),
+            (vec![2.0, 4.0, 6.0, 8.0, 10.0], 2.0)
+        );
+        let (ticks, step) = nice_ticks(0.000001, 1.0, 4);
+        assert_eq!(step, 0.25);
+        assert_eq!(ticks, vec![0.25, 0.5, 0.75, 1.0]);
+        // Reversed and empty ranges.
+        assert_eq!(nice_ticks(10.0, 2.0, 4).0.len(), 5);
+        assert_eq!(nice_ticks(3.0, 3.0, 4), (vec![3.0], 1.0));
+    }
+
+    #[test]
+    fn tick_labels_have_enough_decimals() {
+        assert_eq!(format_tick(4.0, 2.0), "4");
+        assert_eq!(format_tick(0.5, 0.25), "0.50");
+        assert_eq!(format_tick(0.3, 0.1), "0.3");
+        assert_eq!(format_tick(-0.00001, 0.1), "0.0");
+    }
+
+    #[test]
+    fn contours_cross_cell_edges() {
+        // A 2x2 grid rising from 0 on the left to 1 on the right.
+        let segments = contour_segments(&[0.0, 1.0, 0.0, 1.0], 2, 2, 0.25);
+        assert_eq!(segments, vec![((0.25, 0.0), (0.25, 1.0))]);
+        // Nothing crosses a level outside the values, or cells with NaN.
+        assert!(contour_segments(&[0.0, 1.0, 0.0, 1.0], 2, 2, 2.0).is_empty());
+        assert!(contour_segments(&[0.0, 1.0, f32::NAN, 1.0], 2, 2, 0.5).is_empty());
+        // A saddle makes two segments.
+        assert_eq!(contour_segments(&[1.0, 0.0, 0.0, 1.0], 2, 2, 0.5).len(), 2);
+    }
+
+    #[test]
+    fn parses_contour_levels() {
+        assert_eq!(parse_levels("0.9, 95%,100 %"), Ok(vec![0.9, 0.95, 1.0]));
+        assert!(parse_levels("1.5").is_err());
+        assert!(parse_levels("90").is_err());
+        assert!(parse_levels("150%").is_err());
+        assert!(parse_levels("-0.1").is_err());
+        assert!(parse_levels("0.9,").is_err());
+    }
+
+    #[test]
+    fn contours_close_around_peaks() {
+        let mut values = vec![0.0; 25];
+        values[12] = 1.0;
+        let segments = contour_segments(&values, 5, 5, 0.5);
+        assert_eq!(segments.len(), 4);
+        for (a, b) in segments {
+            for p in &[a, b] {
+                let distance = (p.0 - 2.0).abs() + (p.1 - 2.0).abs();
+                assert!((distance - 0.5).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn columns_normalize_to_their_maximum() {
+        let grid = Grid {
+            interval_range: (2.0, 10.0),
+            interval_cells: 2,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        };
+        let relative = normalize_columns(&[1.0, f32::NAN, 4.0, 2.0], &grid);
+        assert_eq!(relative[0], 0.25);
+        assert_eq!(relative[2], 1.0);
+        assert_eq!(relative[3], 1.0);
+    }
+
+    #[test]
+    fn unwritable_charts_are_errors() {
+        let grid = Grid {
+            interval_range: (2.0, 10.0),
+            interval_cells: 2,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        };
+        let mut args = cli::Args::parse(vec!["chart".to_string()].into_iter()).unwrap();
+        let options = ChartOptions::from_args(&mut args, &Params::default()).unwrap();
+        for path in &["/nonexistent/dir/chart.png", "/nonexistent/dir/chart.svg"] {
+            assert!(render_chart(path, &[1.0, 2.0, 3.0, 4.0], &grid, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn long_axis_labels_widen_the_margin() {
+        let grid = Grid {
+            interval_range: (1000.0001, 1000.0009),
+            interval_cells: 2,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        };
+        let mut args = cli::Args::parse(vec!["chart".to_string()].into_iter()).unwrap();
+        let options = ChartOptions::from_args(&mut args, &Params::default()).unwrap();
+        let path = ::std::env::temp_dir().join("anki_sim_test_labels.png");
+        let path = path.to_str().unwrap();
+        let result = render_chart(path, &[1.0, 2.0, 3.0, 4.0], &grid, &options);
+        let _ = ::std::fs::remove_file(path);
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn layout_marks_the_best_optimum() {
+        let grid = Grid {
+            interval_range: (1.0, 3.0),
+            interval_cells: 3,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        };
+        let mut args = cli::Args::parse(vec!["chart".to_string()].into_iter()).unwrap();
+        let options = ChartOptions::from_args(&mut args, &Params::default()).unwrap();
+        // Column 0 peaks at interval factor 2, column 1 higher at 3.
+        let layout = Layout::new(&[1.0, 1.0, 2.0, 2.0, 1.0, 3.0], &grid, &options);
+        assert_eq!(layout.optima, vec![(0.0, 1.0), (1.0, 2.0)]);
+        assert_eq!(layout.global, Some((1.0, 2.0)));
+        assert_eq!(layout.x_ticks.first().map(|t| t.0), Some(0.0));
+        assert_eq!(layout.y_ticks.last().map(|t| t.0), Some(1.0));
+    }
+}
@@ -0,0 +1,590 @@
+use std::str::FromStr;
+
+use params::{Params, PARAM_NAMES};
+use scenario::{Entry, Scenario};
+
+/// Every command, for telling sections for other commands from typos.
+const COMMANDS: &[&str] = &[
+    "chart",
+    "slice",
+    "run",
+    "daily",
+    "lifetime",
+    "inspect",
+    "ridge",
+    "sweep",
+    "optimize",
+    "plan",
+    "calibrate",
+    "compare",
+    "render",
+    "diff",
+];
+
+const GRID_OPTIONS: &[&str] = &[
+    "interval_range",
+    "interval_cells",
+    "retention_range",
+    "retention_cells",
+];
+
+const AXIS_KINDS: &[&str] = &["linear", "log", "list"];
+
+/// Sections of saved sweep data, which can be rerun as a scenario.
+const DATA_SECTIONS: &[&str] = &["metric.", "std_err.", "samples"];
+
+pub const USAGE: &str = "\
+Usage: anki_sim <command> [--scenario <file>] [options]
+
+Commands:
+    chart      Sweep interval factor vs measured retention and write a chart.
+    slice      Print metrics across interval factors for measured retentions.
+    run        Run a single simulation and print its metrics.
+    daily      Run a single simulation and write a record of every day.
+    lifetime   Run a finite deck until its reviews settle and report its cost.
+    inspect    Run a single simulation and describe the deck at the end.
+    ridge      Find the optimal interval factor for each measured retention.
+    sweep      Sweep any parameters over any number of axes and write a table.
+    optimize   Find the parameter values that maximize a metric.
+    plan       Find the new cards per day needed to know a target by a day.
+    calibrate  Fit retention and time costs to an Anki review log.
+    compare    Run both engines with the same settings and print their metrics.
+    render     Draw a chart from a saved sweep data file without re-simulating.
+    diff       Chart the difference or ratio between two sweeps.
+    help       Print this message.
+
+Scenario files:
+    --scenario <file>                      Load settings from a scenario file,
+                                           TOML or, ending in .json, JSON.
+                                           Flags given on the command line
+                                           override the file's values.
+
+Simulation options (all commands):
+    --engine <analytical|monte-carlo>      [default: analytical]
+    --interval-factor <f>                  [default: 2.5]
+    --lapse-interval-factor <f>            Fixed lapse interval multiplier.
+    --lapse-interval-power <f>             Lapse multiplier as interval_factor^p
+                                           [default: -0.5]
+    --measured-retention <f>               [default: 0.9]
+    --measured-retention-interval <f>      Interval factor at which the measured
+                                           retention applies [default: 2.5]
+    --difficulty-variance <f>              [default: 0.0]
+    --max-lapses <n>                       [default: 8]
+    --seconds-per-new-card <f>             [default: 120]
+    --seconds-per-review-card <f>          [default: 20]
+    --seconds-per-lapsed-card <f>          [default: 20]
+    --days <n>                             [default: 365]
+    --new-cards-per-day <n>                [default: 1000]
+    --deck-cards <n>                       Make the deck finite: new cards
+                                           stop once n have been added.
+    --target-known-cards <n>               Goal for the days_to_target and
+                                           hours_to_target metrics, which
+                                           are NaN if it isn't reached.
+    --seed <n>                             Seed the Monte Carlo engine's
+                                           random numbers for repeatable runs.
+    --starting-deck <path>                 Start from the studied cards of an
+                                           Anki collection.anki2 file, or a
+                                           CSV export of its cards table,
+                                           instead of an empty deck.  Day 1
+                                           is Anki's today.
+    --collection-created <unix time>       The crt column of the col table,
+                                           needed to place the due days of a
+                                           CSV export.
+
+Grid options (chart, ridge, slice, sweep):
+    --interval-range <min,max>             [default: 2.0,10.0]
+    --interval-cells <n>                   [default: 65 (chart, sweep), 33 (slice)]
+    --retention-range <min,max>            [default: 0.000001,1.0]
+    --retention-cells <n>                  [default: 101]
+
+Optimize options (plus the replicate options below):
+    --vary <param>=<min>,<max>             Parameter to optimize and its bounds.
+                                           Repeatable [default:
+                                           interval_factor=1.5,10]
+    --metric <name>                        [default: cards_learned_per_hour]
+    --minimize                             Minimize the metric instead.
+    --replicates <n>                       Simulations averaged per point
+                                           [default: 1 (analytical),
+                                           8 (monte-carlo)]
+    --points <n>                           Points sampled per round [default: 9]
+    --rounds <n>                           [default: 4]
+
+Plan options (plus the replicate options below):
+    --target <n>                           Known cards to reach
+                                           [default: --target-known-cards]
+    --by <day>                             Day to reach them by
+                                           [default: --days]
+
+Chart options (chart, ridge, render):
+    --output <path>                        PNG, or SVG if the path ends in
+                                           .svg [default: yar.png]
+    --metric <name>                        Metric to chart
+                                           [default: cards_learned_per_hour]
+    --stat <mean|std-err|cv>               Chart the mean over replicates, its
+                                           standard error or its coefficient
+                                           of variation.  The last two need
+                                           --replicates [default: mean]
+    --scale <mode>                         What the colors show [default:
+                                           columns]:
+                                             columns: % of the column best
+                                             rows: % of the row best
+                                             global: % of the best cell
+                                             loss: % lost vs the column best
+                                             absolute: the metric's values
+    --normalize-slices <true|false>        Older spelling: true is columns,
+                                           false is absolute.
+    --range <min,max>                      Fixed color range, in the scale's
+                                           units.  Values outside it clip.
+    --clip <percent>                       Auto range ignores this percent of
+                                           values at each end [default: 0]
+    --log                                  Logarithmic color scale.
+    --ridge                                Draw the optimal interval factor
+                                           of each column as a red line.
+    --contours <l1,l2,...>                 Draw contours at these fractions
+                                           of each column's maximum, e.g.
+                                           0.9,0.95,0.99 or 90%,95%,99%.
+    --optima                               Mark each column's optimum with a
+                                           dot and the global one with a ring.
+    --colormap <name>                      gray, viridis, magma or diverging
+                                           [default: viridis]
+    --title <text>                         [default: metric and engine]
+    --magnification <n>                    Pixels per grid cell [default: 10]
+
+Slice options:
+    --retentions <r1,r2,...>               Measured retentions to slice at
+                                           [default: --measured-retention]
+    --metric <name>                        Metric to plot
+                                           [default: cards_learned_per_hour]
+    --plot <path>                          Also write an SVG line plot of the
+                                           metric, one line per retention.
+
+Run options:
+    --revlog <path>                        Record every answer of a Monte
+                                           Carlo run in Anki's revlog schema:
+                                           SQLite if the path ends in .anki2,
+                                           .db or .sqlite (which mustn't exist
+                                           yet), else CSV.  Day 1 is today
+                                           for --collection-created, else
+                                           the Unix epoch.
+
+Daily options:
+    --output <path>                        Write the records to a file instead
+                                           of stdout.
+    --format <csv|jsonl>                   [default: from the file extension,
+                                           else csv]
+
+Lifetime options (needs --deck-cards):
+    --settle <percent>                     Stop once a year's review time is
+                                           below this percent of the first
+                                           year's after the last new card
+                                           [default: 5]
+    --max-days <n>                         Give up after n days
+                                           [default: 36500]
+    --output <path>                        Also write a record of every day
+                                           after the last new card.
+    --format <csv|jsonl>                   [default: from the file extension,
+                                           else csv]
+
+Inspect options:
+    --forecast <n>                         Days of future due cards to count
+                                           [default: 30]
+    --output <path>                        Write the deck's histograms and
+                                           forecast as JSON.
+    --chart <path>                         Draw them as SVG bar charts.
+
+Calibrate options:
+    --revlog <path>                        Anki collection (collection.anki2)
+                                           or CSV export of its revlog table.
+    --fit <retention|times|all>            Fit the measured retention, the
+                                           seconds per new, review and lapsed
+                                           card, or both [default: all].
+                                           Retention is fitted on interval
+                                           factor, so only reviews following
+                                           a successful spaced review count;
+                                           the rest of the revlog is left out.
+    --output <path>                        Write a scenario file with the
+                                           fitted values.
+
+Ridge options:
+    --output <path>                        Write the table to a file instead of
+                                           stdout.
+    --off <percent>                        Report the efficiency lost by being
+                                           this far off the optimum
+                                           [default: 10]
+    --chart <path>                         Also write a PNG chart with the
+                                           ridge drawn over it.  Accepts the
+                                           chart options above.
+
+Replicates (run, chart, ridge, sweep, diff, plan, optimize):
+    --replicates <n>                       Run each Monte Carlo simulation n
+                                           times.  `run` prints the mean,
+                                           standard error and percentiles;
+                                           sweeps report means and keep
+                                           standard errors.  The analytical
+                                           engine is deterministic and runs
+                                           once [default: 1]
+    --tolerance <se>                       Keep adding replicates until the
+                                           standard error of
+                                           cards_learned_per_hour is below
+                                           this.  --replicates is then the
+                                           minimum [default: 4]
+    --max-replicates <n>                   Most replicates per cell with
+                                           --tolerance [default: 64]
+    --common-random-numbers                Give every cell of a sweep, or
+                                           point optimize samples, the same
+                                           random numbers, card by card, so
+                                           neighboring cells differ only by
+                                           their settings.
+    --antithetic                           Make each replicate a pair of runs,
+                                           the second with the mirror image
+                                           of the first's random numbers.
+
+Saving sweeps (chart, ridge, sweep):
+    --data <path>                          Also save every metric of every
+                                           cell, the axes and the parameters
+                                           to a data file for `render`,
+                                           with standard errors if there
+                                           were replicates and each cell's
+                                           replicate count with --tolerance.
+
+Render options:
+    --data <path>                          Data file to draw.  Accepts the
+                                           chart options above.
+    --interval-range <min,max>             Crop to these interval factors.
+    --retention-range <min,max>            Crop to these measured retentions.
+
+Diff options (plus the chart options above):
+    --a <file>, --b <file>                 The sweeps to compare, as data
+                                           files or scenario files to run.
+                                           Both must cover the same grid.
+    --mode <difference|ratio>              Chart B - A or B / A
+                                           [default: difference]
+    --replicates <n>                       Runs of each Monte Carlo scenario
+                                           [default: 4]
+    --significance <z>                     Gray out cells whose change is
+                                           within z standard errors of zero.
+                                           Needs replicates [default: 2]
+    --output <path>                        [default: diff.png]
+
+Sweep options:
+    --axis <param>=<kind>:<values>         Add a sweep axis.  Repeatable; the
+                                           first axis varies slowest.  Kinds:
+                                             linear:<min>,<max>,<cells>
+                                             log:<min>,<max>,<cells>
+                                             list:<v1>,<v2>,...
+                                           Without any axes, sweeps the grid
+                                           options above.
+    --output <path>                        Write the table to a file instead of
+                                           stdout.
+";
+
+/// Parsed command line: a command followed by `--name value` options.
+///
+/// Options are consumed as they are read, so that anything left over at
+/// the end can be reported as unrecognized.  Values loaded from a scenario
+/// file act as defaults that the command line overrides.
+pub struct Args {
+    pub command: String,
+    options: Vec<(String, String)>,
+    defaults: Vec<(String, String)>,
+    /// Options from the scenario's section for this command, with the
+    /// error to report if the command doesn't take them.
+    scenario_options: Vec<(String, String)>,
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Args, String> {
+        let mut args = args.peekable();
+        let command = args.next().unwrap_or_else(|| "help".to_string());
+        let mut options = Vec::new();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            let arg = &arg[2..];
+            if let Some(i) = arg.find('=') {
+                options.push((arg[..i].to_string(), arg[(i + 1)..].to_string()));
+            } else {
+                // A bare flag with no value means "true".
+                let has_value = match args.peek() {
+                    Some(next) => !next.starts_with("--"),
+                    None => false,
+                };
+                let value = if has_value {
+                    args.next().unwrap()
+                } else {
+                    "true".to_string()
+                };
+                options.push((arg.to_string(), value));
+            }
+        }
+
+        Ok(Args {
+            command,
+            options,
+            defaults: Vec::new(),
+            scenario_options: Vec::new(),
+        })
+    }
+
+    pub fn from_env() -> Result<Args, String> {
+        Args::parse(::std::env::args().skip(1))
+    }
+
+    /// Removes and returns the last value given for an option, falling
+    /// back to the scenario defaults.
+    pub fn take(&mut self, name: &str) -> Option<String> {
+        let given = take_last(&mut self.options, name);
+        let default = take_last(&mut self.defaults, name);
+        given.or(default)
+    }
+
+    /// Removes and returns every value given for a repeatable option.
+    /// Values from the command line replace the scenario's entirely.
+    pub fn take_all(&mut self, name: &str) -> Vec<String> {
+        let given = take_every(&mut self.options, name);
+        let defaults = take_every(&mut self.defaults, name);
+        if given.is_empty() {
+            defaults
+        } else {
+            given
+        }
+    }
+
+    /// Loads the scenario named by `--scenario`, if any, as defaults.
+    ///
+    /// Top-level keys must be simulation parameters.  `[axis.<param>]`
+    /// sections become `--axis` options, and the `[grid]` section and the
+    /// section named after the current command supply defaults for the
+    /// matching options.  Sections for other commands are skipped, but
+    /// unknown sections and keys are errors.
+    pub fn load_scenario(&mut self) -> Result<(), String> {
+        let path = match take_last(&mut self.options, "scenario") {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let scenario = Scenario::load(&path)?;
+        let unknown = |what: &str, e: &Entry| {
+            format!("{}: line {}: unknown {} '{}'", path, e.line, what, e.key)
+        };
+
+        for e in scenario.section("") {
+            if !PARAM_NAMES.contains(&e.key.as_str()) {
+                return Err(unknown("parameter", e));
+            }
+            self.defaults
+                .push((e.key.replace('_', "-"), e.value.to_arg()));
+        }
+        for section in &scenario.sections {
+            let name = section.name.as_str();
+            if let Some(param) = name.strip_prefix("axis.") {
+                for e in &section.entries {
+                    if !AXIS_KINDS.contains(&e.key.as_str()) {
+                        return Err(unknown("axis kind", e));
+                    }
+                    self.defaults.push((
+                        "axis".to_string(),
+                        format!("{}={}:{}", param, e.key, e.value.to_arg()),
+                    ));
+                }
+            } else if name == "grid" {
+                for e in &section.entries {
+                    if !GRID_OPTIONS.contains(&e.key.as_str()) {
+                        return Err(unknown("grid option", e));
+                    }
+                    self.defaults
+                        .push((e.key.replace('_', "-"), e.value.to_arg()));
+                }
+            } else if name == self.command {
+                // Checked by finish(), once the command has taken the
+                // options it knows.
+                for e in &section.entries {
+                    let option = e.key.replace('_', "-");
+                    self.defaults.push((option.clone(), e.value.to_arg()));
+                    self.scenario_options.push((option, unknown("option", e)));
+                }
+            } else if !name.is_empty()
+                && !COMMANDS.contains(&name)
+                && !DATA_SECTIONS.iter().any(|d| name.starts_with(d))
+            {
+                return Err(format!(
+                    "{}: line {}: unknown section [{}]",
+                    path, section.line, name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets a default for an option that neither the command line nor the
+    /// scenario gave, for commands whose defaults differ from the usual.
+    pub fn default_to(&mut self, name: &str, value: &str) {
+        self.defaults.insert(0, (name.to_string(), value.to_string()));
+    }
+
+    pub fn get<T: FromStr>(&mut self, name: &str, default: T) -> Result<T, String> {
+        match self.take(name) {
+            Some(v) => v
+                .parse::<T>()
+                .map_err(|_| format!("invalid value '{}' for --{}", v, name)),
+            None => Ok(default),
+        }
+    }
+
+    /// Reads a `min,max` pair.
+    pub fn get_range(&mut self, name: &str, default: (f32, f32)) -> Result<(f32, f32), String> {
+        match self.take(name) {
+            Some(v) => parse_range(&v).map_err(|e| format!("--{}: {}", name, e)),
+            None => Ok(default),
+        }
+    }
+
+    /// Applies any simulation parameter options to `params`.
+    pub fn apply_params(&mut self, params: &mut Params) -> Result<(), String> {
+        for name in PARAM_NAMES {
+            if let Some(v) = self.take(&name.replace('_', "-")) {
+                params.set(name, &v)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors if any options were given, on the command line or in the
+    /// scenario's section for the command, that nothing consumed.
+    pub fn finish(self) -> Result<(), String> {
+        if let Some((n, _)) = self.options.first() {
+            return Err(format!(
+                "unrecognized option '--{}' for command '{}'",
+                n, self.command
+            ));
+        }
+        for (option, err) in &self.scenario_options {
+            if self.defaults.iter().any(|(n, _)| n == option) {
+                return Err(format!("{} for command '{}'", err, self.command));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn take_every(list: &mut Vec<(String, String)>, name: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    list.retain(|(n, v)| {
+        if n == name {
+            values.push(v.clone());
+            false
+        } else {
+            true
+        }
+    });
+    values
+}
+
+fn take_last(list: &mut Vec<(String, String)>, name: &str) -> Option<String> {
+    let mut value = None;
+    list.retain(|(n, v)| {
+        if n == name {
+            value = Some(v.clone());
+            false
+        } else {
+            true
+        }
+    });
+    value
+}
+
+pub fn parse_range(s: &str) -> Result<(f32, f32), String> {
+    let mut parts = s.split(',');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(a), Some(b), None) => match (a.trim().parse(), b.trim().parse()) {
+            (Ok(a), Ok(b)) => Ok((a, b)),
+            _ => Err(format!("invalid range '{}'", s)),
+        },
+        _ => Err(format!("expected 'min,max', got '{}'", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Args {
+        Args::parse(line.split_whitespace().map(|s| s.to_string())).unwrap()
+    }
+
+    #[test]
+    fn parses_values_flags_and_equals() {
+        let mut a = args("chart --days 30 --normalize-slices --metric=known_cards");
+        assert_eq!(a.command, "chart");
+        assert_eq!(a.get("days", 365u32), Ok(30));
+        assert_eq!(a.get("normalize-slices", false), Ok(true));
+        assert_eq!(a.take("metric"), Some("known_cards".to_string()));
+        assert!(a.finish().is_ok());
+    }
+
+    #[test]
+    fn last_value_wins_and_defaults_apply() {
+        let mut a = args("run --days 10 --days 20");
+        a.default_to("days", "5");
+        a.default_to("seed", "7");
+        assert_eq!(a.get("days", 365u32), Ok(20));
+        assert_eq!(a.get("seed", 0u64), Ok(7));
+        assert_eq!(a.get("max-lapses", 8u32), Ok(8));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(Args::parse(vec!["run".to_string(), "stray".to_string()].into_iter()).is_err());
+        assert!(args("run --days x").get("days", 1u32).is_err());
+        assert!(args("run --bogus 1").finish().is_err());
+    }
+
+    #[test]
+    fn missing_command_is_help() {
+        assert_eq!(args("").command, "help");
+    }
+
+    /// Loads `text` as the scenario of `command` and finishes the
+    /// arguments after taking `taken`.
+    fn load(command: &str, name: &str, text: &str, taken: &[&str]) -> Result<(), String> {
+        let path = ::std::env::temp_dir().join(format!("anki_sim_test_{}", name));
+        ::std::fs::write(&path, text).unwrap();
+        let mut a = args(&format!("{} --scenario {}", command, path.display()));
+        let result = a.load_scenario().and_then(|_| {
+            for option in taken {
+                a.take(option);
+            }
+            let mut params = Params::default();
+            a.apply_params(&mut params)
+        });
+        ::std::fs::remove_file(&path).unwrap();
+        result.and_then(|_| a.finish())
+    }
+
+    #[test]
+    fn scenarios_reject_unknown_keys() {
+        let ok = "days = 3\n[grid]\ninterval_cells = 3\n[chart]\nfoo = 1\n[run]\nreplicates = 2\n";
+        assert_eq!(load("run", "ok.toml", ok, &["replicates"]), Ok(()));
+        let typo = "days = 3\n[run]\nreplicate = 2\n";
+        let err = load("run", "typo.toml", typo, &["replicates"]).unwrap_err();
+        assert!(err.ends_with("line 3: unknown option 'replicate' for command 'run'"));
+        let err = load("run", "param.toml", "new_card_per_day = 3\n", &[]).unwrap_err();
+        assert!(err.ends_with("line 1: unknown parameter 'new_card_per_day'"));
+        let err = load("run", "grid.toml", "[grid]\ninterval_cell = 3\n", &[]).unwrap_err();
+        assert!(err.ends_with("line 2: unknown grid option 'interval_cell'"));
+        let err = load("run", "section.toml", "[rnu]\n", &[]).unwrap_err();
+        assert!(err.ends_with("line 1: unknown section [rnu]"));
+        let json = "{\n  \"run\": { \"replicate\": 2 }\n}";
+        let err = load("run", "typo.json", json, &["replicates"]).unwrap_err();
+        assert!(err.ends_with("line 2: unknown option 'replicate' for command 'run'"));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("2, 10.5"), Ok((2.0, 10.5)));
+        assert!(parse_range("2").is_err());
+        assert!(parse_range("2,3,4").is_err());
+        assert!(parse_range("a,b").is_err());
+    }
+}
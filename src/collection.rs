@@ -0,0 +1,607 @@
+//! Reading card state and review history from Anki, either CSV exports of
+//! a collection's `cards` and `revlog` tables or the collection's
+//! `collection.anki2` SQLite file itself, and writing simulated review
+//! history in the same forms.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OpenFlags};
+
+/// A studied card's state at the start of a simulation.
+#[derive(Debug, Copy, Clone)]
+pub struct StartingCard {
+    pub interval: f32,
+    /// Set so the card comes due on the same day it's due in Anki, with
+    /// day 1 of the simulation being Anki's today.  Overdue cards are past
+    /// their interval.
+    pub days_since_last_review: f32,
+    pub lapses: u32,
+    pub reviews: u32,
+    /// Anki's ease factor, e.g. 2.5.  The engines schedule with
+    /// `interval_factor` instead, so this is only reported.
+    pub ease: f32,
+}
+
+/// The studied cards of an Anki collection.
+#[derive(Debug, Clone)]
+pub struct StartingDeck {
+    pub path: String,
+    pub cards: Vec<StartingCard>,
+    /// Cards that haven't been studied yet, which aren't included.
+    pub new_cards: u32,
+    /// Suspended cards, which aren't included either.
+    pub suspended: u32,
+}
+
+impl StartingDeck {
+    /// Loads a CSV export of the `cards` table or a `collection.anki2`
+    /// file.  A CSV export doesn't say when the collection was created,
+    /// which Anki counts due days from, so `created` (the `crt` column of
+    /// the `col` table, in Unix seconds) must be given for it.
+    pub fn load(path: &str, created: Option<u64>) -> Result<StartingDeck, String> {
+        let rows = if is_sqlite(path)? {
+            read_sqlite(path).map_err(|e| format!("{}: {}", path, e))?
+        } else {
+            read_csv(path)?
+        };
+        let today = match (rows.created, created) {
+            (_, Some(c)) | (Some(c), None) => Some(anki_today(c)),
+            (None, None) => None,
+        };
+
+        let mut deck = StartingDeck {
+            path: path.to_string(),
+            cards: Vec::new(),
+            new_cards: 0,
+            suspended: 0,
+        };
+        for card in &rows.cards {
+            if card.queue == -1 {
+                deck.suspended += 1;
+                continue;
+            }
+            // Cards in filtered decks keep their home deck's due day in
+            // `odue`.
+            let due = if card.odid != 0 && card.odue != 0 {
+                card.odue
+            } else {
+                card.due
+            };
+            let interval = (card.ivl.max(1)) as f32;
+            let days_since_last_review = match card.kind {
+                0 => {
+                    deck.new_cards += 1;
+                    continue;
+                }
+                // Learning and relearning cards are due again within the
+                // day, so are due on the first day.
+                1 | 3 => interval,
+                2 => match today {
+                    Some(today) => interval - (due - today) as f32,
+                    None => {
+                        return Err(format!(
+                            "{}: placing due days from a CSV export needs \
+                             collection_created (the crt column of the col table)",
+                            path
+                        ))
+                    }
+                },
+                k => return Err(format!("{}: unknown card type {}", path, k)),
+            };
+            deck.cards.push(StartingCard {
+                interval,
+                days_since_last_review: days_since_last_review.max(0.0),
+                lapses: card.lapses.max(0) as u32,
+                reviews: card.reps.max(0) as u32,
+                ease: card.factor as f32 / 1000.0,
+            });
+        }
+        Ok(deck)
+    }
+
+    /// The average ease of the cards, as a guide for `interval_factor`, or
+    /// None if no card has one.
+    pub fn mean_ease(&self) -> Option<f32> {
+        let eased: Vec<f32> = self
+            .cards
+            .iter()
+            .map(|c| c.ease)
+            .filter(|&e| e > 0.0)
+            .collect();
+        if eased.is_empty() {
+            return None;
+        }
+        Some(eased.iter().sum::<f32>() / eased.len() as f32)
+    }
+}
+
+/// The columns of Anki's `cards` table that matter here.
+struct CardRow {
+    kind: i64,   // 0 new, 1 learning, 2 review, 3 relearning.
+    queue: i64,  // -1 for suspended.
+    due: i64,    // Days since the collection was created, for review cards.
+    ivl: i64,    // Days, for review cards.
+    factor: i64, // Ease in permille.
+    reps: i64,
+    lapses: i64,
+    odid: i64, // Home deck of cards in filtered decks, else 0.
+    odue: i64,
+}
+
+struct CardRows {
+    cards: Vec<CardRow>,
+    created: Option<u64>,
+}
+
+/// Anki's day number for today: whole days since the collection was
+/// created.
+fn anki_today(created: u64) -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (now as i64 - created as i64).div_euclid(86400)
+}
+
+/// When Anki's today started, in Unix seconds.
+pub fn anki_today_start(created: u64) -> i64 {
+    created as i64 + anki_today(created) * 86400
+}
+
+/// Whether the file is an SQLite database rather than text.
+pub fn is_sqlite(path: &str) -> Result<bool, String> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path).map_err(|e| format!("couldn't open '{}': {}", path, e))?;
+    let read = file
+        .read(&mut header)
+        .map_err(|e| format!("couldn't read '{}': {}", path, e))?;
+    Ok(read == header.len() && &header == b"SQLite format 3\0")
+}
+
+/// Opens a collection without changing it.  Anki may have it open too.
+pub fn open_sqlite(path: &str) -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+fn read_sqlite(path: &str) -> rusqlite::Result<CardRows> {
+    let db = open_sqlite(path)?;
+    let created: i64 = db.query_row("SELECT crt FROM col", [], |row| row.get(0))?;
+    let mut query =
+        db.prepare("SELECT type, queue, due, ivl, factor, reps, lapses, odid, odue FROM cards")?;
+    let cards = query
+        .query_map([], |row| {
+            Ok(CardRow {
+                kind: row.get(0)?,
+                queue: row.get(1)?,
+                due: row.get(2)?,
+                ivl: row.get(3)?,
+                factor: row.get(4)?,
+                reps: row.get(5)?,
+                lapses: row.get(6)?,
+                odid: row.get(7)?,
+                odue: row.get(8)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(CardRows {
+        cards,
+        created: Some(created as u64),
+    })
+}
+
+fn read_csv(path: &str) -> Result<CardRows, String> {
+    let table = CsvTable::load(path)?;
+    let column = |name: &str| table.column(name);
+    let (kind, queue, due, ivl) = (
+        column("type")?,
+        column("queue")?,
+        column("due")?,
+        column("ivl")?,
+    );
+    let (factor, reps, lapses) = (column("factor")?, column("reps")?, column("lapses")?);
+    let (odid, odue) = (table.column("odid").ok(), table.column("odue").ok());
+
+    let mut cards = Vec::new();
+    for (line, row) in table.rows() {
+        let get = |i: usize| table.integer(line, row, i);
+        let optional = |i: Option<usize>| i.map_or(Ok(0), &get);
+        cards.push(CardRow {
+            kind: get(kind)?,
+            queue: get(queue)?,
+            due: get(due)?,
+            ivl: get(ivl)?,
+            factor: get(factor)?,
+            reps: get(reps)?,
+            lapses: get(lapses)?,
+            odid: optional(odid)?,
+            odue: optional(odue)?,
+        });
+    }
+    Ok(CardRows {
+        cards,
+        created: None,
+    })
+}
+
+/// Kinds of `revlog` entries.
+pub const LEARNING: i64 = 0;
+pub const REVIEW: i64 = 1;
+pub const RELEARNING: i64 = 2;
+pub const FILTERED: i64 = 3;
+pub const MANUAL: i64 = 4;
+
+/// One entry of Anki's `revlog` table: an answer to a card, or a manual
+/// reschedule.
+#[derive(Debug, Copy, Clone)]
+pub struct Review {
+    pub id: i64, // When it happened, in Unix milliseconds.
+    pub card: i64,
+    /// The button pressed: 1 again, 2 hard, 3 good, 4 easy.
+    pub ease: i64,
+    /// The interval after and before the answer, in days, or negative
+    /// seconds for learning steps.
+    pub interval: i64,
+    pub last_interval: i64,
+    pub factor: i64,
+    pub time: i64, // Milliseconds spent answering.
+    pub kind: i64,
+}
+
+impl Review {
+    /// Days since the Unix epoch, with fractions.
+    pub fn day(&self) -> f64 {
+        self.id as f64 / 86_400_000.0
+    }
+
+    pub fn recalled(&self) -> bool {
+        self.ease > 1
+    }
+}
+
+/// Loads a CSV export of the `revlog` table or a `collection.anki2` file,
+/// ordered by card and then time.
+pub fn load_revlog(path: &str) -> Result<Vec<Review>, String> {
+    let mut reviews = if is_sqlite(path)? {
+        read_sqlite_revlog(path).map_err(|e| format!("{}: {}", path, e))?
+    } else {
+        read_csv_revlog(path)?
+    };
+    if reviews.is_empty() {
+        return Err(format!("{}: no reviews", path));
+    }
+    reviews.sort_by_key(|r| (r.card, r.id));
+    Ok(reviews)
+}
+
+fn read_sqlite_revlog(path: &str) -> rusqlite::Result<Vec<Review>> {
+    let db = open_sqlite(path)?;
+    let mut query =
+        db.prepare("SELECT id, cid, ease, ivl, lastIvl, factor, time, type FROM revlog")?;
+    let reviews = query
+        .query_map([], |row| {
+            Ok(Review {
+                id: row.get(0)?,
+                card: row.get(1)?,
+                ease: row.get(2)?,
+                interval: row.get(3)?,
+                last_interval: row.get(4)?,
+                factor: row.get(5)?,
+                time: row.get(6)?,
+                kind: row.get(7)?,
+            })
+        })?
+        .collect();
+    reviews
+}
+
+fn read_csv_revlog(path: &str) -> Result<Vec<Review>, String> {
+    let table = CsvTable::load(path)?;
+    let column = |name: &str| table.column(name);
+    let (id, card, ease, interval) = (
+        column("id")?,
+        column("cid")?,
+        column("ease")?,
+        column("ivl")?,
+    );
+    let (last_interval, factor, time, kind) = (
+        column("lastivl")?,
+        column("factor")?,
+        column("time")?,
+        column("type")?,
+    );
+
+    let mut reviews = Vec::new();
+    for (line, row) in table.rows() {
+        let get = |i: usize| table.integer(line, row, i);
+        reviews.push(Review {
+            id: get(id)?,
+            card: get(card)?,
+            ease: get(ease)?,
+            interval: get(interval)?,
+            last_interval: get(last_interval)?,
+            factor: get(factor)?,
+            time: get(time)?,
+            kind: get(kind)?,
+        });
+    }
+    Ok(reviews)
+}
+
+/// Anki's `revlog` table, as it creates it.
+const REVLOG_TABLE: &str = "CREATE TABLE revlog (
+    id integer primary key,
+    cid integer not null,
+    usn integer not null,
+    ease integer not null,
+    ivl integer not null,
+    lastIvl integer not null,
+    factor integer not null,
+    time integer not null,
+    type integer not null
+)";
+
+/// Writes reviews as a `revlog` table: to a new SQLite database if the
+/// path ends in `.anki2`, `.db` or `.sqlite`, else as CSV.  Databases are
+/// never written over, so a real collection can't be changed by mistake.
+pub fn write_revlog(path: &str, reviews: &[Review]) -> Result<(), String> {
+    check_revlog_path(path)?;
+    if has_sqlite_extension(path) {
+        write_sqlite_revlog(path, reviews).map_err(|e| format!("{}: {}", path, e))
+    } else {
+        File::create(path)
+            .and_then(|f| write_csv_revlog(&mut BufWriter::new(f), reviews))
+            .map_err(|e| format!("couldn't write '{}': {}", path, e))
+    }
+}
+
+/// Fails if `write_revlog()` would refuse `path`, so that a long
+/// simulation can be checked before it runs.
+pub fn check_revlog_path(path: &str) -> Result<(), String> {
+    if has_sqlite_extension(path) && fs::metadata(path).is_ok() {
+        return Err(format!("'{}' already exists", path));
+    }
+    Ok(())
+}
+
+fn has_sqlite_extension(path: &str) -> bool {
+    [".anki2", ".db", ".sqlite"]
+        .iter()
+        .any(|e| path.ends_with(e))
+}
+
+fn write_sqlite_revlog(path: &str, reviews: &[Review]) -> rusqlite::Result<()> {
+    let mut db = Connection::open(path)?;
+    let tx = db.transaction()?;
+    tx.execute(REVLOG_TABLE, [])?;
+    {
+        let mut insert = tx.prepare("INSERT INTO revlog VALUES (?, ?, 0, ?, ?, ?, ?, ?, ?)")?;
+        for r in reviews {
+            insert.execute([
+                r.id,
+                r.card,
+                r.ease,
+                r.interval,
+                r.last_interval,
+                r.factor,
+                r.time,
+                r.kind,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Writes the same columns as `sqlite3 -header -csv` would export.
+fn write_csv_revlog<W: Write>(out: &mut W, reviews: &[Review]) -> io::Result<()> {
+    writeln!(out, "id,cid,usn,ease,ivl,lastIvl,factor,time,type")?;
+    for r in reviews {
+        writeln!(
+            out,
+            "{},{},0,{},{},{},{},{},{}",
+            r.id, r.card, r.ease, r.interval, r.last_interval, r.factor, r.time, r.kind
+        )?;
+    }
+    out.flush()
+}
+
+/// A CSV file with a header row, as exported by `sqlite3 -header -csv`.
+pub struct CsvTable {
+    path: String,
+    header: Vec<String>,
+    rows: Vec<(usize, Vec<String>)>, // With their line numbers.
+}
+
+impl CsvTable {
+    pub fn load(path: &str) -> Result<CsvTable, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("couldn't read '{}': {}", path, e))?;
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty());
+        let header = match lines.next() {
+            Some((_, l)) => split_csv(l)
+                .iter()
+                .map(|h| h.trim().to_lowercase())
+                .collect(),
+            None => return Err(format!("{}: empty file", path)),
+        };
+        Ok(CsvTable {
+            path: path.to_string(),
+            header,
+            rows: lines.map(|(i, l)| (i + 1, split_csv(l))).collect(),
+        })
+    }
+
+    /// The index of a column, by its header name.
+    pub fn column(&self, name: &str) -> Result<usize, String> {
+        self.header
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("{}: no '{}' column", self.path, name))
+    }
+
+    /// The rows after the header, with their line numbers.
+    pub fn rows<'a>(&'a self) -> impl Iterator<Item = (usize, &'a [String])> + 'a {
+        self.rows.iter().map(|(n, r)| (*n, &r[..]))
+    }
+
+    /// A whole-number field of a row.
+    pub fn integer(&self, line: usize, row: &[String], column: usize) -> Result<i64, String> {
+        let field = row.get(column).map_or("", |f| f.trim());
+        field.parse().map_err(|_| {
+            format!(
+                "{}:{}: invalid {} '{}'",
+                self.path, line, self.header[column], field
+            )
+        })
+    }
+}
+
+/// Splits a CSV line into fields, allowing quoted fields with commas and
+/// doubled quotes in them.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(::std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `text` to a temporary file named `name` and runs `f` on its
+    /// path.
+    fn with_file<T, F: FnOnce(&str) -> T>(name: &str, text: &str, f: F) -> T {
+        let path = ::std::env::temp_dir().join(format!("anki_sim_test_{}", name));
+        let path = path.to_str().unwrap();
+        fs::write(path, text).unwrap();
+        let result = f(path);
+        fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(split_csv("a,,b"), vec!["a", "", "b"]);
+        assert_eq!(
+            split_csv("1,\"x, \"\"y\"\"\",2"),
+            vec!["1", "x, \"y\"", "2"]
+        );
+    }
+
+    #[test]
+    fn tables_name_bad_columns_and_fields() {
+        let text = "ID,Ease\n\n1,3\n2,x\n";
+        with_file("table.csv", text, |path| {
+            let table = CsvTable::load(path).unwrap();
+            assert_eq!(table.column("ease"), Ok(1));
+            assert!(table.column("ivl").unwrap_err().contains("no 'ivl' column"));
+            let rows: Vec<_> = table.rows().collect();
+            assert_eq!(table.integer(rows[0].0, rows[0].1, 1), Ok(3));
+            let err = table.integer(rows[1].0, rows[1].1, 1).unwrap_err();
+            assert!(err.ends_with(":4: invalid ease 'x'"), "{}", err);
+        });
+        assert!(with_file("empty.csv", "\n", CsvTable::load).is_err());
+    }
+
+    #[test]
+    fn loads_cards_from_csv() {
+        // Created a day ago, so today is Anki day 1.
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            - 86_400;
+        let text = "type,queue,due,ivl,factor,reps,lapses\n\
+                    0,0,5,0,0,0,0\n\
+                    2,2,4,10,2500,6,1\n\
+                    2,-1,4,10,2500,6,1\n\
+                    1,1,0,0,0,1,0\n";
+        let deck = with_file("cards.csv", text, |p| StartingDeck::load(p, Some(created)));
+        let deck = deck.unwrap();
+        assert_eq!(
+            (deck.new_cards, deck.suspended, deck.cards.len()),
+            (1, 1, 2)
+        );
+        // Due in 3 days with a 10 day interval.
+        assert_eq!(deck.cards[0].days_since_last_review, 7.0);
+        assert_eq!(deck.cards[0].lapses, 1);
+        // The learning card has no ease, so isn't averaged.
+        assert_eq!(deck.mean_ease(), Some(2.5));
+
+        let no_created = with_file("cards.csv", text, |p| StartingDeck::load(p, None));
+        assert!(no_created.unwrap_err().contains("collection_created"));
+    }
+
+    #[test]
+    fn reads_revlogs_in_card_order() {
+        let text = "id,cid,usn,ease,ivl,lastIvl,factor,time,type\n\
+                    200,2,0,3,1,0,2500,5000,1\n\
+                    300,1,0,1,-600,10,2500,8000,1\n\
+                    100,2,0,3,-60,0,0,9000,0\n";
+        let reviews = with_file("revlog.csv", text, load_revlog).unwrap();
+        let ids: Vec<i64> = reviews.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![300, 100, 200]);
+        assert!(!reviews[0].recalled());
+        assert_eq!(reviews[0].interval, -600);
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_revlogs() {
+        let header = "id,cid,usn,ease,ivl,lastIvl,factor,time,type\n";
+        let err = |name: &str, text: &str| with_file(name, text, load_revlog).unwrap_err();
+        assert!(err("empty.csv", "").contains("empty file"));
+        assert!(err("header.csv", header).contains("no reviews"));
+        let missing = "id,cid,ease,ivl,lastIvl,factor,time\n1,1,3,1,0,2500,5000\n";
+        assert!(err("missing.csv", missing).contains("no 'type' column"));
+        let bad = format!("{}1,1,0,3,1,0,2500,5.5,1\n", header);
+        assert!(err("bad.csv", &bad).contains(":2: invalid time '5.5'"));
+        let short = format!("{}1,1,0,3\n", header);
+        assert!(err("short.csv", &short).contains("invalid ivl ''"));
+    }
+
+    #[test]
+    fn decks_without_eases_have_no_mean() {
+        let deck = StartingDeck {
+            path: String::new(),
+            cards: Vec::new(),
+            new_cards: 3,
+            suspended: 0,
+        };
+        assert_eq!(deck.mean_ease(), None);
+    }
+
+    #[test]
+    fn never_writes_over_databases() {
+        with_file("existing.anki2", "", |path| {
+            assert!(check_revlog_path(path)
+                .unwrap_err()
+                .contains("already exists"));
+            assert!(write_revlog(path, &[]).is_err());
+            assert_eq!(fs::metadata(path).unwrap().len(), 0);
+        });
+        with_file("existing.csv", "", |path| {
+            assert_eq!(check_revlog_path(path), Ok(()));
+        });
+    }
+}
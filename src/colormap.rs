@@ -0,0 +1,122 @@
+//! Color maps for turning chart values into colors.
+
+use std::str::FromStr;
+
+use image::Rgb;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Colormap {
+    Gray,
+    Viridis,
+    Magma,
+    /// Blue through white to red, for values centered on a midpoint.
+    Diverging,
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Colormap, String> {
+        match s {
+            "gray" | "grey" | "grayscale" => Ok(Colormap::Gray),
+            "viridis" => Ok(Colormap::Viridis),
+            "magma" => Ok(Colormap::Magma),
+            "diverging" => Ok(Colormap::Diverging),
+            _ => Err(format!(
+                "unknown color map '{}' (expected gray, viridis, magma or diverging)",
+                s
+            )),
+        }
+    }
+}
+
+// Evenly spaced samples of each map, from 0.0 to 1.0.  Viridis and magma
+// are sampled from matplotlib's maps, diverging from ColorBrewer's RdBu.
+const VIRIDIS: [Rgb; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+const MAGMA: [Rgb; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const DIVERGING: [Rgb; 9] = [
+    [5, 48, 97],
+    [33, 102, 172],
+    [67, 147, 195],
+    [146, 197, 222],
+    [247, 247, 247],
+    [244, 165, 130],
+    [214, 96, 77],
+    [178, 24, 43],
+    [103, 0, 31],
+];
+
+const GRAY: [Rgb; 2] = [[0, 0, 0], [255, 255, 255]];
+
+impl Colormap {
+    /// The color for `t`, which is clamped to [0, 1].  NaN maps to a
+    /// neutral mid gray.
+    pub fn sample(&self, t: f32) -> Rgb {
+        if t.is_nan() {
+            return [128, 128, 128];
+        }
+        let table: &[Rgb] = match *self {
+            Colormap::Gray => &GRAY,
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Diverging => &DIVERGING,
+        };
+
+        let pos = t.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+        let i = (pos as usize).min(table.len() - 2);
+        let alpha = pos - i as f32;
+        let mut color = [0u8; 3];
+        for (c, (a, b)) in color
+            .iter_mut()
+            .zip(table[i].iter().zip(table[i + 1].iter()))
+        {
+            *c = ((*a as f32 * (1.0 - alpha)) + (*b as f32 * alpha)).round() as u8;
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_interpolate_and_clamp() {
+        assert_eq!(Colormap::Gray.sample(0.0), [0, 0, 0]);
+        assert_eq!(Colormap::Gray.sample(0.5), [128, 128, 128]);
+        assert_eq!(Colormap::Gray.sample(2.0), [255, 255, 255]);
+        assert_eq!(Colormap::Viridis.sample(-1.0), VIRIDIS[0]);
+        assert_eq!(Colormap::Viridis.sample(1.0), VIRIDIS[8]);
+        assert_eq!(Colormap::Diverging.sample(0.5), DIVERGING[4]);
+        assert_eq!(Colormap::Magma.sample(f32::NAN), [128, 128, 128]);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("grey".parse(), Ok(Colormap::Gray));
+        assert_eq!("magma".parse(), Ok(Colormap::Magma));
+        assert!("jet".parse::<Colormap>().is_err());
+    }
+}
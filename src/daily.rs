@@ -0,0 +1,303 @@
+//! Day-by-day records of a simulation run.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Lower bounds of the interval buckets that cards are counted in, with
+/// the column name of each.
+pub const INTERVAL_BUCKETS: [(f32, &str); 5] = [
+    (0.0, "interval_1_6"),
+    (7.0, "interval_7_20"),
+    (21.0, "interval_21_89"),
+    (90.0, "interval_90_364"),
+    (365.0, "interval_365_plus"),
+];
+
+/// Which bucket of `INTERVAL_BUCKETS` an interval falls in.
+pub fn bucket(interval: f32) -> usize {
+    INTERVAL_BUCKETS
+        .iter()
+        .rposition(|&(min, _)| interval >= min)
+        .unwrap_or(0)
+}
+
+/// Running totals and deck state at the end of a day, as reported by an
+/// engine.  Counts are fractional for the analytical engine.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Snapshot {
+    pub cards_added: f64,
+    pub reviews: f64,
+    pub lapses: f64,
+    /// Cards dropped from the deck for lapsing past the maximum.
+    pub removed: f64,
+    pub new_seconds: f64,
+    pub review_seconds: f64,
+    pub deck_size: f64,
+    /// The sum of every card's recall probability.
+    pub known_cards: f64,
+    /// Cards due at the start of the next day.
+    pub due: f64,
+    pub interval_counts: [f64; 5],
+}
+
+/// What happened on one day, and the deck at the end of it.
+#[derive(Debug, Copy, Clone)]
+pub struct DayRecord {
+    pub day: u32, // Starting from 1.
+    pub new_cards: f64,
+    pub reviews: f64,
+    pub lapses: f64,
+    pub removed: f64,
+    pub time_spent: f64, // In hours, new cards and reviews together.
+    pub deck_size: f64,
+    /// Cards expected to be recalled, from each card's recall probability.
+    /// Both engines count them the same way.
+    pub known_cards: f64,
+    /// Cards waiting for review at the start of the next day.  Neither
+    /// engine has a daily review limit, so this is the next day's load.
+    pub backlog: f64,
+    pub interval_counts: [f64; 5],
+}
+
+impl Snapshot {
+    /// Adds `weight` times another snapshot's counts to this one.
+    pub fn add(&mut self, other: &Snapshot, weight: f64) {
+        self.cards_added += other.cards_added * weight;
+        self.reviews += other.reviews * weight;
+        self.lapses += other.lapses * weight;
+        self.removed += other.removed * weight;
+        self.new_seconds += other.new_seconds * weight;
+        self.review_seconds += other.review_seconds * weight;
+        self.deck_size += other.deck_size * weight;
+        self.known_cards += other.known_cards * weight;
+        self.due += other.due * weight;
+        for (t, c) in self
+            .interval_counts
+            .iter_mut()
+            .zip(other.interval_counts.iter())
+        {
+            *t += c * weight;
+        }
+    }
+}
+
+/// Whole-deck snapshots from those of a single cohort of new cards.
+///
+/// The analytical engine follows only the cards added on the first day.
+/// Every day's cohort behaves the same, so on day `d` the deck is the sum
+/// of the cohort's states at ages 1 to `d`, and the deck's running totals
+/// are the sums of the cohort's.  `sizes` scales each day's cohort, for
+/// decks that run out of new cards.
+pub fn superpose(cohort: &[Snapshot], sizes: &[f64]) -> Vec<Snapshot> {
+    (0..cohort.len())
+        .map(|d| {
+            let mut total = Snapshot::default();
+            // Day j + 1's cohort is d - j + 1 days old.
+            for (j, &size) in sizes.iter().enumerate().take(d + 1) {
+                if size != 0.0 {
+                    total.add(&cohort[d - j], size);
+                }
+            }
+            total
+        })
+        .collect()
+}
+
+/// Turns end-of-day snapshots into per-day records.
+pub fn records(snapshots: &[Snapshot]) -> Vec<DayRecord> {
+    let mut prev = Snapshot::default();
+    snapshots
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let record = DayRecord {
+                day: i as u32 + 1,
+                new_cards: s.cards_added - prev.cards_added,
+                reviews: s.reviews - prev.reviews,
+                lapses: s.lapses - prev.lapses,
+                removed: s.removed - prev.removed,
+                time_spent: ((s.new_seconds - prev.new_seconds)
+                    + (s.review_seconds - prev.review_seconds))
+                    / 3600.0,
+                deck_size: s.deck_size,
+                known_cards: s.known_cards,
+                backlog: s.due,
+                interval_counts: s.interval_counts,
+            };
+            prev = *s;
+            record
+        })
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "json-lines" | "ndjson" => Ok(Format::JsonLines),
+            _ => Err(format!("unknown format '{}' (expected csv or jsonl)", s)),
+        }
+    }
+}
+
+impl Format {
+    /// The format a file name suggests, defaulting to CSV.
+    pub fn from_path(path: &str) -> Format {
+        let path = path.to_lowercase();
+        if path.ends_with(".jsonl") || path.ends_with(".ndjson") || path.ends_with(".json") {
+            Format::JsonLines
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+/// Column names, in output order.
+fn columns() -> Vec<&'static str> {
+    let mut names = vec![
+        "day",
+        "new_cards",
+        "reviews",
+        "lapses",
+        "removed",
+        "time_spent",
+        "deck_size",
+        "known_cards",
+        "backlog",
+    ];
+    names.extend(INTERVAL_BUCKETS.iter().map(|b| b.1));
+    names
+}
+
+fn values(r: &DayRecord) -> Vec<f64> {
+    let mut values = vec![
+        r.day as f64,
+        r.new_cards,
+        r.reviews,
+        r.lapses,
+        r.removed,
+        r.time_spent,
+        r.deck_size,
+        r.known_cards,
+        r.backlog,
+    ];
+    values.extend_from_slice(&r.interval_counts);
+    values
+}
+
+pub fn write_records<W: Write>(
+    out: &mut W,
+    records: &[DayRecord],
+    format: Format,
+) -> io::Result<()> {
+    let names = columns();
+    if format == Format::Csv {
+        writeln!(out, "{}", names.join(","))?;
+    }
+    for r in records {
+        let values: Vec<String> = values(r).iter().map(|v| v.to_string()).collect();
+        match format {
+            Format::Csv => writeln!(out, "{}", values.join(","))?,
+            Format::JsonLines => {
+                let fields: Vec<String> = names
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(n, v)| format!("\"{}\":{}", n, json_number(v)))
+                    .collect();
+                writeln!(out, "{{{}}}", fields.join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// JSON has no NaN or infinity, so those become null.
+pub fn json_number(v: &str) -> &str {
+    match v.parse::<f64>() {
+        Ok(n) if n.is_finite() => v,
+        _ => "null",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(cards_added: f64, reviews: f64, deck_size: f64) -> Snapshot {
+        Snapshot {
+            cards_added,
+            reviews,
+            new_seconds: cards_added * 3600.0,
+            deck_size,
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn buckets_intervals() {
+        assert_eq!(bucket(0.5), 0);
+        assert_eq!(bucket(7.0), 1);
+        assert_eq!(bucket(89.9), 2);
+        assert_eq!(bucket(1000.0), 4);
+    }
+
+    #[test]
+    fn superposes_daily_cohorts() {
+        let cohort = [snapshot(1.0, 0.0, 1.0), snapshot(1.0, 2.0, 1.0)];
+        // The second day's cohort is half the size.
+        let deck = superpose(&cohort, &[1.0, 0.5]);
+        assert_eq!(deck[0].cards_added, 1.0);
+        assert_eq!(deck[1].cards_added, 1.5);
+        assert_eq!(deck[1].reviews, 2.0);
+        assert_eq!(deck[1].deck_size, 1.5);
+    }
+
+    #[test]
+    fn records_are_daily_changes() {
+        let records = records(&[snapshot(2.0, 0.0, 2.0), snapshot(3.0, 5.0, 3.0)]);
+        assert_eq!(records[1].day, 2);
+        assert_eq!(records[1].new_cards, 1.0);
+        assert_eq!(records[1].reviews, 5.0);
+        assert_eq!(records[1].time_spent, 1.0);
+        assert_eq!(records[1].deck_size, 3.0);
+    }
+
+    #[test]
+    fn writes_csv_and_json_lines() {
+        let records = records(&[Snapshot {
+            known_cards: f64::NAN,
+            ..snapshot(1.0, 0.0, 1.0)
+        }]);
+        let mut csv = Vec::new();
+        write_records(&mut csv, &records, Format::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("day,new_cards,"));
+        assert!(lines[0].contains(",deck_size,known_cards,backlog,"));
+        assert!(lines[1].starts_with("1,1,0,"));
+
+        let mut json = Vec::new();
+        write_records(&mut json, &records, Format::JsonLines).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"day\":1,\"new_cards\":1,"));
+        assert!(json.contains("\"known_cards\":null"));
+    }
+
+    #[test]
+    fn json_numbers_are_finite_or_null() {
+        assert_eq!(json_number("1.5"), "1.5");
+        assert_eq!(json_number("NaN"), "null");
+        assert_eq!(json_number("inf"), "null");
+        assert_eq!(Format::from_path("days.JSONL"), Format::JsonLines);
+        assert_eq!(Format::from_path("days.txt"), Format::Csv);
+    }
+}
@@ -0,0 +1,307 @@
+//! What the deck looks like on a given day: histograms of its cards and
+//! a forecast of their reviews.
+
+use std::io::{self, Write};
+
+use daily::json_number;
+use svg::Bars;
+
+/// Cards with at least this interval are mature, as in Anki.
+pub const MATURE_INTERVAL: u32 = 21;
+
+/// Interval bins double in width, from 1 day up to this many days and
+/// beyond.
+pub const INTERVAL_BINS: usize = 11;
+
+/// Per-card retention ratio bins, each this wide.
+const RETENTION_BIN_WIDTH: f32 = 0.05;
+const RETENTION_BINS: usize = 20;
+
+/// Counts of cards in the deck at the end of a day.  Counts are fractional
+/// for the analytical engine.
+#[derive(Debug, Clone)]
+pub struct DeckState {
+    pub day: u32,
+    pub young: f64,
+    pub mature: f64,
+    /// Cards by interval, in doubling bins: 1, 2-3, 4-7 days and so on.
+    pub intervals: Vec<f64>,
+    /// Cards by how many times they've lapsed.
+    pub lapses: Vec<f64>,
+    /// Cards whose next review falls on each of the following days,
+    /// starting with tomorrow.  Overdue cards count as due tomorrow.  Like
+    /// Anki's "Future Due" graph, only each card's next review is counted.
+    pub due: Vec<f64>,
+    /// Cards by their chance of being remembered at each review, in bins
+    /// of 5%.  Harder cards lapse more and are dropped sooner, so this
+    /// shows which cards survive.
+    pub retention: Vec<f64>,
+}
+
+impl DeckState {
+    pub fn new(day: u32, max_lapses: u32, forecast_days: u32) -> DeckState {
+        DeckState {
+            day,
+            young: 0.0,
+            mature: 0.0,
+            intervals: vec![0.0; INTERVAL_BINS],
+            lapses: vec![0.0; max_lapses as usize + 1],
+            due: vec![0.0; forecast_days as usize],
+            retention: vec![0.0; RETENTION_BINS],
+        }
+    }
+
+    /// Counts `cards` cards with the given state in the histograms and
+    /// forecast.  Engines count young and mature cards themselves.
+    pub fn add_cards(
+        &mut self,
+        cards: f64,
+        interval: f32,
+        days_since_last_review: f32,
+        lapses: u32,
+        retention_ratio: f32,
+    ) {
+        self.intervals[interval_bin(interval)] += cards;
+        let last = self.lapses.len() - 1;
+        self.lapses[(lapses as usize).min(last)] += cards;
+
+        // A card is due once its days since review reach its interval, and
+        // those days only start counting tomorrow.
+        let days = (interval - days_since_last_review).ceil().max(0.0) as usize + 1;
+        if days <= self.due.len() {
+            self.due[days - 1] += cards;
+        }
+
+        let bin = (retention_ratio / RETENTION_BIN_WIDTH) as usize;
+        self.retention[bin.min(RETENTION_BINS - 1)] += cards;
+    }
+
+    /// Adds `weight` times another state's counts to this one.
+    pub fn add(&mut self, other: &DeckState, weight: f64) {
+        self.young += other.young * weight;
+        self.mature += other.mature * weight;
+        for (counts, others) in [
+            (&mut self.intervals, &other.intervals),
+            (&mut self.lapses, &other.lapses),
+            (&mut self.due, &other.due),
+            (&mut self.retention, &other.retention),
+        ] {
+            for (c, o) in counts.iter_mut().zip(others.iter()) {
+                *c += o * weight;
+            }
+        }
+    }
+
+    pub fn deck_size(&self) -> f64 {
+        self.young + self.mature
+    }
+
+    /// Labeled bars for each histogram, in the order they're charted.
+    pub fn bars(&self) -> Vec<Bars> {
+        let intervals = self
+            .intervals
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (interval_label(i), c as f32))
+            .collect();
+        let lapses = self
+            .lapses
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (i.to_string(), c as f32))
+            .collect();
+        let due = self
+            .due
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| ((i + 1).to_string(), c as f32))
+            .collect();
+        let retention = self
+            .retention
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (retention_label(i), c as f32))
+            .collect();
+        vec![
+            Bars {
+                title: "Cards by interval".to_string(),
+                x_label: "Interval (days)".to_string(),
+                bars: intervals,
+            },
+            Bars {
+                title: "Cards by lapses".to_string(),
+                x_label: "Lapses".to_string(),
+                bars: lapses,
+            },
+            Bars {
+                title: "Future due".to_string(),
+                x_label: "Days from now".to_string(),
+                bars: due,
+            },
+            Bars {
+                title: "Young and mature cards".to_string(),
+                x_label: format!("Mature at {} days", MATURE_INTERVAL),
+                bars: vec![
+                    ("young".to_string(), self.young as f32),
+                    ("mature".to_string(), self.mature as f32),
+                ],
+            },
+            Bars {
+                title: "Surviving cards by retention".to_string(),
+                x_label: "Chance of recall at each review".to_string(),
+                bars: retention,
+            },
+        ]
+    }
+
+    /// Prints a short summary.
+    pub fn print(&self) {
+        println!("day = {}", self.day);
+        println!("deck_size = {}", self.deck_size());
+        println!("young = {}", self.young);
+        println!("mature = {}", self.mature);
+        let due = &self.due[..self.due.len().min(7)];
+        println!(
+            "due_next_{}_days = {}",
+            due.len(),
+            due.iter()
+                .map(|d| format!("{:.1}", d))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    /// Writes the state as a JSON object.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let list = |values: &[f64]| {
+            let values: Vec<String> = values
+                .iter()
+                .map(|v| json_number(&v.to_string()).to_string())
+                .collect();
+            format!("[{}]", values.join(","))
+        };
+        let labels = |labels: Vec<String>| {
+            let labels: Vec<String> = labels.iter().map(|l| format!("\"{}\"", l)).collect();
+            format!("[{}]", labels.join(","))
+        };
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"day\": {},", self.day)?;
+        writeln!(
+            out,
+            "  \"deck_size\": {},",
+            json_number(&self.deck_size().to_string())
+        )?;
+        writeln!(
+            out,
+            "  \"young\": {},",
+            json_number(&self.young.to_string())
+        )?;
+        writeln!(
+            out,
+            "  \"mature\": {},",
+            json_number(&self.mature.to_string())
+        )?;
+        writeln!(out, "  \"mature_interval\": {},", MATURE_INTERVAL)?;
+        writeln!(
+            out,
+            "  \"interval_bins\": {},",
+            labels((0..self.intervals.len()).map(interval_label).collect())
+        )?;
+        writeln!(out, "  \"intervals\": {},", list(&self.intervals))?;
+        writeln!(out, "  \"lapses\": {},", list(&self.lapses))?;
+        writeln!(out, "  \"due\": {},", list(&self.due))?;
+        writeln!(
+            out,
+            "  \"retention_bins\": {},",
+            labels((0..self.retention.len()).map(retention_label).collect())
+        )?;
+        writeln!(out, "  \"retention\": {}", list(&self.retention))?;
+        writeln!(out, "}}")
+    }
+}
+
+/// The whole deck's state from the states of a single cohort at each age,
+/// the way `daily::superpose` builds whole-deck snapshots.
+pub fn superpose(cohort: &[DeckState], sizes: &[f64]) -> DeckState {
+    let last = cohort.last().unwrap();
+    let mut total = DeckState::new(
+        last.day,
+        last.lapses.len() as u32 - 1,
+        last.due.len() as u32,
+    );
+    let d = cohort.len() - 1;
+    // Day j + 1's cohort is d - j + 1 days old.
+    for (j, &size) in sizes.iter().enumerate().take(d + 1) {
+        if size != 0.0 {
+            total.add(&cohort[d - j], size);
+        }
+    }
+    total
+}
+
+/// The bin of `intervals` that counts cards with this interval.
+pub fn interval_bin(interval: f32) -> usize {
+    let bin = interval.max(1.0).log2().floor() as usize;
+    bin.min(INTERVAL_BINS - 1)
+}
+
+pub fn interval_label(bin: usize) -> String {
+    let (lo, hi) = (1u32 << bin, (1u32 << (bin + 1)) - 1);
+    if bin == INTERVAL_BINS - 1 {
+        format!("{}+", lo)
+    } else if lo == hi {
+        lo.to_string()
+    } else {
+        format!("{}-{}", lo, hi)
+    }
+}
+
+fn retention_label(bin: usize) -> String {
+    let lo = (bin as f32 * RETENTION_BIN_WIDTH * 100.0).round();
+    format!("{}-{}%", lo, lo + (RETENTION_BIN_WIDTH * 100.0).round())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bins_intervals_by_doubling() {
+        assert_eq!(interval_bin(0.5), 0);
+        assert_eq!(interval_bin(3.9), 1);
+        assert_eq!(interval_bin(4.0), 2);
+        assert_eq!(interval_bin(1e6), INTERVAL_BINS - 1);
+        assert_eq!(interval_label(0), "1");
+        assert_eq!(interval_label(2), "4-7");
+        assert_eq!(interval_label(INTERVAL_BINS - 1), "1024+");
+    }
+
+    #[test]
+    fn forecasts_when_cards_are_due() {
+        let mut state = DeckState::new(1, 2, 5);
+        // Due in 2 days, overdue, and beyond the forecast.
+        state.add_cards(1.0, 4.0, 2.0, 0, 0.9);
+        state.add_cards(2.0, 4.0, 6.0, 5, 0.93);
+        state.add_cards(4.0, 30.0, 0.0, 1, 1.0);
+        assert_eq!(state.due, vec![2.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(state.lapses, vec![1.0, 4.0, 2.0]);
+        assert_eq!(state.retention[18], 3.0);
+        assert_eq!(state.retention[RETENTION_BINS - 1], 4.0);
+    }
+
+    #[test]
+    fn superposes_cohorts_by_age() {
+        let cohort: Vec<DeckState> = (1..=2)
+            .map(|day| {
+                let mut state = DeckState::new(day, 0, 1);
+                state.young = day as f64;
+                state
+            })
+            .collect();
+        // One-day-old cards from day 2's half-size cohort, and two-day-old
+        // cards from day 1's.
+        let deck = superpose(&cohort, &[1.0, 0.5]);
+        assert_eq!(deck.day, 2);
+        assert_eq!(deck.young, 2.5);
+    }
+}
@@ -0,0 +1,222 @@
+//! Differences between two sweeps over the same chart grid.
+
+use std::str::FromStr;
+
+use chart::{self, Grid};
+use cli;
+use params::Params;
+use scenario::Scenario;
+use stats::Stat;
+use sweep::{self, Sampling, SweepData};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// B - A.
+    Difference,
+    /// B / A.
+    Ratio,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Kind, String> {
+        match s {
+            "difference" | "diff" => Ok(Kind::Difference),
+            "ratio" => Ok(Kind::Ratio),
+            _ => Err(format!(
+                "unknown comparison '{}' (expected difference or ratio)",
+                s
+            )),
+        }
+    }
+}
+
+/// One side of a comparison: a sweep over a chart grid.
+pub struct Side {
+    pub data: SweepData,
+    pub grid: Grid,
+}
+
+impl Side {
+    /// Loads a saved sweep data file, or runs the sweep a scenario file
+    /// describes.
+    pub fn load(path: &str, sampling: Sampling) -> Result<Side, String> {
+        let is_data = Scenario::load(path)?
+            .sections
+            .iter()
+            .any(|s| s.name.starts_with("metric."));
+        if is_data {
+            let mut data = SweepData::load(path)?;
+            if data.axes.len() == 2 && data.axes[0].name == "interval_factor" {
+                data = data.reorder(&[1, 0]);
+            }
+            let grid = Grid::from_axes(&data.axes).map_err(|e| format!("{}: {}", path, e))?;
+            return Ok(Side { data, grid });
+        }
+
+        // Read the scenario the same way the sweeping commands would.
+        let mut args = cli::Args::parse(
+            vec!["diff", "--scenario", path]
+                .into_iter()
+                .map(|s| s.to_string()),
+        )?;
+        args.load_scenario()?;
+        let mut params = Params::default();
+        args.apply_params(&mut params)?;
+        let axes = args
+            .take_all("axis")
+            .iter()
+            .map(|spec| sweep::Axis::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let grid = if axes.is_empty() {
+            Grid::from_args(&mut args, 65)?
+        } else {
+            Grid::from_axes(&axes).map_err(|e| format!("{}: {}", path, e))?
+        };
+        args.finish()?;
+
+        let data = SweepData::run(&params, &grid.axes(), sampling);
+        Ok(Side { data, grid })
+    }
+
+    /// Per-cell mean and standard error of `metric`, in chart order.  The
+    /// standard error is zero for sweeps without replicates.
+    fn chart(&self, metric: &str) -> (Vec<f32>, Vec<f32>) {
+        let engine = self.data.params.engine;
+        let means = chart::chart_values(&self.data.results, &self.grid, metric, engine);
+        let errs = match self.data.values(Stat::StdErr) {
+            Ok(errs) => chart::chart_values(&errs, &self.grid, metric, engine),
+            Err(_) => vec![0.0; means.len()],
+        };
+        (means, errs)
+    }
+}
+
+/// Compares `metric` between the two sides cell by cell.  Cells where the
+/// change is within `z` standard errors of none are masked out as NaN,
+/// which needs replicates on at least one side.
+pub fn compare(a: &Side, b: &Side, metric: &str, kind: Kind, z: f32) -> Result<Vec<f32>, String> {
+    if a.grid != b.grid {
+        return Err("the two sweeps must cover the same grid".to_string());
+    }
+    let (a_mean, a_err) = a.chart(metric);
+    let (b_mean, b_err) = b.chart(metric);
+
+    Ok((0..a_mean.len())
+        .map(|i| {
+            let (va, vb) = (a_mean[i], b_mean[i]);
+            let (value, err) = match kind {
+                Kind::Difference => (vb - va, a_err[i].hypot(b_err[i])),
+                Kind::Ratio => {
+                    // Delta method: relative errors add in quadrature.
+                    let r = vb / va;
+                    (r, r.abs() * (a_err[i] / va).hypot(b_err[i] / vb))
+                }
+            };
+            let change = match kind {
+                Kind::Difference => value,
+                Kind::Ratio => value - 1.0,
+            };
+            if err > 0.0 && change.abs() < z * err {
+                f32::NAN
+            } else {
+                value
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use params::{Engine, Metrics};
+
+    /// A Monte Carlo sweep over a 2x1 grid with the given
+    /// cards_learned_per_hour means and standard errors.
+    fn side(means: &[f32], errs: Option<&[f32]>) -> Side {
+        let grid = Grid {
+            interval_range: (2.0, 3.0),
+            interval_cells: 1,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        };
+        let metrics = |values: &[f32]| -> Vec<Metrics> {
+            values
+                .iter()
+                .map(|&v| {
+                    let mut m = Metrics::default();
+                    *m.get_mut("cards_learned_per_hour").unwrap() = v;
+                    m
+                })
+                .collect()
+        };
+        Side {
+            data: SweepData {
+                params: Params {
+                    engine: Engine::MonteCarlo,
+                    ..Params::default()
+                },
+                axes: grid.axes().to_vec(),
+                results: metrics(means),
+                std_errs: errs.map(metrics),
+                samples: None,
+                starting_deck: None,
+            },
+            grid,
+        }
+    }
+
+    #[test]
+    fn compares_cell_by_cell() {
+        let (a, b) = (side(&[2.0, 4.0], None), side(&[3.0, 2.0], None));
+        let metric = "cards_learned_per_hour";
+        assert_eq!(
+            compare(&a, &b, metric, Kind::Difference, 2.0).unwrap(),
+            vec![1.0, -2.0]
+        );
+        assert_eq!(
+            compare(&a, &b, metric, Kind::Ratio, 2.0).unwrap(),
+            vec![1.5, 0.5]
+        );
+    }
+
+    #[test]
+    fn masks_changes_within_the_noise() {
+        let a = side(&[2.0, 4.0], Some(&[0.3, 0.3]));
+        let b = side(&[3.0, 2.0], Some(&[0.4, 0.4]));
+        // The errors combine to 0.5, so only the change of 2 is 2.5 errors
+        // away from none.
+        let diff = compare(&a, &b, "cards_learned_per_hour", Kind::Difference, 2.5).unwrap();
+        assert!(diff[0].is_nan());
+        assert_eq!(diff[1], -2.0);
+    }
+
+    #[test]
+    fn grids_must_match() {
+        let a = side(&[2.0, 4.0], None);
+        let mut b = side(&[3.0, 2.0], None);
+        b.grid.interval_range = (2.0, 4.0);
+        assert!(compare(&a, &b, "cards_learned_per_hour", Kind::Ratio, 0.0).is_err());
+    }
+
+    #[test]
+    fn scenario_sides_reject_unknown_options() {
+        let path = ::std::env::temp_dir().join("anki_sim_test_diff_side.toml");
+        let path = path.to_str().unwrap();
+        let load = |text: &str| {
+            ::std::fs::write(path, text).unwrap();
+            let side = Side::load(path, Sampling::fixed(1));
+            ::std::fs::remove_file(path).unwrap();
+            side
+        };
+        let scenario = "engine = \"analytical\"\ndays = 10\n\
+                        [grid]\ninterval_cells = 2\nretention_cells = 2\n";
+        let side = load(scenario).ok().unwrap();
+        assert_eq!(side.data.results.len(), 4);
+        let err = load(&format!("{}[diff]\nbogus = 1\n", scenario))
+            .err()
+            .unwrap();
+        assert!(err.contains("unknown option 'bogus'"), "{}", err);
+    }
+}
@@ -0,0 +1,131 @@
+//! A simple RGBA raster image with just enough drawing operations for
+//! labeled charts.
+
+use std::fs::File;
+use std::io;
+
+use png_encode_mini;
+
+use font;
+
+pub type Rgb = [u8; 3];
+
+pub const BLACK: Rgb = [0, 0, 0];
+pub const WHITE: Rgb = [255, 255, 255];
+pub const RED: Rgb = [255, 32, 32];
+
+/// Pixels are stored top row first.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, background: Rgb) -> Image {
+        let mut pixels = vec![255u8; width * height * 4];
+        for p in pixels.chunks_mut(4) {
+            p[..3].copy_from_slice(&background);
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Sets a pixel, ignoring coordinates outside the image.
+    pub fn set(&mut self, x: isize, y: isize, color: Rgb) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let i = (y as usize * self.width + x as usize) * 4;
+        self.pixels[i..(i + 3)].copy_from_slice(&color);
+    }
+
+    pub fn fill_rect(&mut self, x: isize, y: isize, w: usize, h: usize, color: Rgb) {
+        for yy in 0..h {
+            for xx in 0..w {
+                self.set(x + xx as isize, y + yy as isize, color);
+            }
+        }
+    }
+
+    /// Draws a one pixel wide rectangle outline.
+    pub fn outline_rect(&mut self, x: isize, y: isize, w: usize, h: usize, color: Rgb) {
+        self.fill_rect(x, y, w, 1, color);
+        self.fill_rect(x, y + h as isize - 1, w, 1, color);
+        self.fill_rect(x, y, 1, h, color);
+        self.fill_rect(x + w as isize - 1, y, 1, h, color);
+    }
+
+    /// Draws a line with square "pen" of the given thickness.
+    pub fn line(&mut self, from: (isize, isize), to: (isize, isize), thickness: usize, color: Rgb) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs()).max(1);
+        let offset = (thickness / 2) as isize;
+        for s in 0..(steps + 1) {
+            let x = from.0 + (dx * s) / steps;
+            let y = from.1 + (dy * s) / steps;
+            self.fill_rect(x - offset, y - offset, thickness, thickness, color);
+        }
+    }
+
+    /// Draws text with its top-left corner at `(x, y)`.
+    pub fn text(&mut self, x: isize, y: isize, text: &str, scale: usize, color: Rgb) {
+        for (ci, c) in text.chars().enumerate() {
+            let rows = font::glyph(c);
+            let cx = x + (ci * font::ADVANCE * scale) as isize;
+            for (ry, row) in rows.iter().enumerate() {
+                for rx in 0..font::GLYPH_WIDTH {
+                    if row & (0x10 >> rx) != 0 {
+                        self.fill_rect(
+                            cx + (rx * scale) as isize,
+                            y + (ry * scale) as isize,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws text rotated a quarter turn counter-clockwise, so it reads
+    /// bottom to top.  `(x, y)` is the bottom-left corner of the text.
+    pub fn text_vertical(&mut self, x: isize, y: isize, text: &str, scale: usize, color: Rgb) {
+        for (ci, c) in text.chars().enumerate() {
+            let rows = font::glyph(c);
+            let cy = y - (ci * font::ADVANCE * scale) as isize;
+            for (ry, row) in rows.iter().enumerate() {
+                for rx in 0..font::GLYPH_WIDTH {
+                    if row & (0x10 >> rx) != 0 {
+                        self.fill_rect(
+                            x + (ry * scale) as isize,
+                            cy - ((rx + 1) * scale) as isize,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn write_png(&self, path: &str) -> io::Result<()> {
+        // png_encode_mini expects the bottom row first.
+        let row_len = self.width * 4;
+        let mut flipped = Vec::with_capacity(self.pixels.len());
+        for row in self.pixels.chunks(row_len).rev() {
+            flipped.extend_from_slice(row);
+        }
+        png_encode_mini::write_rgba_from_u8(
+            &mut File::create(path)?,
+            &flipped,
+            self.width as u32,
+            self.height as u32,
+        )
+    }
+}
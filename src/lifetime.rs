@@ -0,0 +1,194 @@
+//! The lifetime cost of a finite deck: every card is introduced, and the
+//! simulation runs on until the reviews have died down.
+
+use daily::DayRecord;
+use params::Params;
+
+/// Days over which reviews are checked for having settled.
+pub const SETTLE_WINDOW: u32 = 365;
+
+pub struct Lifetime {
+    /// Every day through the one on which reviews settled, or through the
+    /// last day simulated if they didn't.
+    pub records: Vec<DayRecord>,
+    /// Days on which new cards were added.
+    pub introduction_days: u32,
+    pub settled: bool,
+    seconds_per_new_card: f64,
+}
+
+impl Lifetime {
+    /// Hours spent on new cards.
+    pub fn new_hours(&self) -> f64 {
+        self.records.iter().map(|r| r.new_cards).sum::<f64>() * self.seconds_per_new_card / 3600.0
+    }
+
+    /// Hours spent on reviews while new cards were still being added.
+    pub fn introduction_review_hours(&self) -> f64 {
+        self.records[..self.introduction_days as usize]
+            .iter()
+            .map(|r| review_hours(r, self.seconds_per_new_card))
+            .sum()
+    }
+
+    /// Hours spent on reviews after the last new card: the deck's
+    /// maintenance cost.
+    pub fn maintenance_hours(&self) -> f64 {
+        self.maintenance()
+            .iter()
+            .map(|r| review_hours(r, self.seconds_per_new_card))
+            .sum()
+    }
+
+    pub fn total_hours(&self) -> f64 {
+        self.records.iter().map(|r| r.time_spent).sum()
+    }
+
+    /// The days after the last new card.
+    pub fn maintenance(&self) -> &[DayRecord] {
+        &self.records[self.introduction_days as usize..]
+    }
+
+    /// Average reviews and minutes of reviews per day over each year after
+    /// the last new card.
+    pub fn yearly_load(&self) -> Vec<(f64, f64)> {
+        self.maintenance()
+            .chunks(365)
+            .map(|year| {
+                let days = year.len() as f64;
+                let reviews: f64 = year.iter().map(|r| r.reviews).sum();
+                let hours: f64 = year
+                    .iter()
+                    .map(|r| review_hours(r, self.seconds_per_new_card))
+                    .sum();
+                (reviews / days, hours * 60.0 / days)
+            })
+            .collect()
+    }
+}
+
+/// Hours spent on reviews on the day of `r`.
+pub fn review_hours(r: &DayRecord, seconds_per_new_card: f64) -> f64 {
+    r.time_spent - r.new_cards * seconds_per_new_card / 3600.0
+}
+
+/// Simulates the finite deck of `base` until its reviews settle: until the
+/// review hours of the last `SETTLE_WINDOW` days are below `settle` times
+/// those of the first `SETTLE_WINDOW` days after the last new card.  Gives
+/// up after `max_days`.
+///
+/// Intervals grow without limit, so surviving cards are reviewed ever more
+/// rarely but never stop entirely.  Lifetime hours therefore depend a
+/// little on `settle`.
+pub fn simulate(base: &Params, settle: f64, max_days: u32) -> Result<Lifetime, String> {
+    let cards = base
+        .deck_cards
+        .ok_or_else(|| "a deck's lifetime needs --deck-cards <n>".to_string())?;
+    if base.new_cards_per_day == 0 {
+        return Err("new_cards_per_day must be at least 1".to_string());
+    }
+    let introduction_days = cards.div_ceil(base.new_cards_per_day);
+    let seconds_per_new_card = base.seconds_per_new_card as f64;
+
+    // Neither engine can stop partway, so run for longer and longer until
+    // the reviews have settled.
+    let mut params = base.clone();
+    params.days = (introduction_days + SETTLE_WINDOW).min(max_days);
+    loop {
+        let records = params.simulate_daily();
+        if let Some(day) = settled_day(&records, introduction_days, settle, seconds_per_new_card) {
+            return Ok(Lifetime {
+                records: records[..day].to_vec(),
+                introduction_days: introduction_days.min(day as u32),
+                settled: true,
+                seconds_per_new_card,
+            });
+        }
+        if params.days >= max_days {
+            return Ok(Lifetime {
+                records,
+                introduction_days: introduction_days.min(params.days),
+                settled: false,
+                seconds_per_new_card,
+            });
+        }
+        params.days = (params.days * 2).min(max_days);
+    }
+}
+
+/// The number of days after which reviews had settled, if they did.
+fn settled_day(
+    records: &[DayRecord],
+    introduction_days: u32,
+    settle: f64,
+    seconds_per_new_card: f64,
+) -> Option<usize> {
+    let start = introduction_days as usize;
+    let window = SETTLE_WINDOW as usize;
+    if records.len() < start + window {
+        return None;
+    }
+    let hours: Vec<f64> = records[start..]
+        .iter()
+        .map(|r| review_hours(r, seconds_per_new_card))
+        .collect();
+    let first: f64 = hours[..window].iter().sum();
+    let mut recent = first;
+    for i in window..hours.len() {
+        recent += hours[i] - hours[i - window];
+        if recent < settle * first {
+            return Some(start + i + 1);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use params::Engine;
+
+    fn params() -> Params {
+        Params {
+            engine: Engine::Analytical,
+            deck_cards: Some(100),
+            new_cards_per_day: 30,
+            ..Params::default()
+        }
+    }
+
+    #[test]
+    fn hours_add_up() {
+        let lifetime = simulate(&params(), 0.5, 5000).unwrap();
+        assert!(lifetime.settled);
+        assert_eq!(lifetime.introduction_days, 4);
+        let parts = lifetime.new_hours()
+            + lifetime.introduction_review_hours()
+            + lifetime.maintenance_hours();
+        assert!((parts - lifetime.total_hours()).abs() < 1e-6);
+        let new_hours = 100.0 * params().seconds_per_new_card as f64 / 3600.0;
+        assert!((lifetime.new_hours() - new_hours).abs() < 1e-6);
+        assert!(lifetime.yearly_load()[0].0 > 0.0);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_day() {
+        let lifetime = simulate(&params(), 1e-9, 400).unwrap();
+        assert!(!lifetime.settled);
+        assert_eq!(lifetime.records.len(), 400);
+    }
+
+    #[test]
+    fn needs_a_finite_deck() {
+        let endless = Params {
+            deck_cards: None,
+            ..params()
+        };
+        assert!(simulate(&endless, 0.5, 1000).is_err());
+        let idle = Params {
+            new_cards_per_day: 0,
+            ..params()
+        };
+        assert!(simulate(&idle, 0.5, 1000).is_err());
+    }
+}
@@ -2,218 +2,620 @@
 
 extern crate png_encode_mini;
 extern crate rand;
+extern crate rusqlite;
 
 mod anki_sim;
 mod anki_sim_ana;
+mod calibrate;
+mod chart;
+mod cli;
+mod collection;
+mod colormap;
+mod daily;
+mod deck_state;
+mod diff;
+mod font;
+mod image;
+mod lifetime;
+mod optimize;
+mod params;
+mod plan;
+mod ridge;
+mod scale;
+mod scenario;
+mod svg;
+mod stats;
+mod sweep;
 
 use std::fs::File;
 use std::io::Write;
 
+use params::{metric_label, Engine, Metrics, Params, METRIC_NAMES};
+
 fn main() {
-    generate_chart(
-        "yar.png",
-        1000,
-        true,
-        (2.0, 10.0),
-        65,
-        (0.000001, 1.0),
-        101,
-        true,
-    );
-    // print_vertical_slice(1000, (2.0, 10.0), 33, 0.8);
+    if let Err(e) = run_command() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
 
-fn generate_chart(
-    path: &str,
-    samples: u32,
-    normalize_slices: bool,
-    interval_range: (f32, f32),
-    interval_cells: u32,
-    retention_range: (f32, f32),
-    retention_cells: u32,
-    use_analytical: bool,
-) {
-    let height = interval_cells as usize;
-    let interval_step = (interval_range.1 - interval_range.0) / (interval_cells - 1) as f32;
-    let width = retention_cells as usize;
-    let retention_step = (retention_range.1 - retention_range.0) / (retention_cells - 1) as f32;
-
-    print!("\n0.0%");
-    let _ = std::io::stdout().flush();
-    let mut chart = vec![0.0f32; height * width];
-    for x in 0..width {
-        let retention_ratio = retention_range.0 + (retention_step * x as f32);
-        for y in 0..height {
-            let interval_factor = interval_range.0 + (interval_step * y as f32);
-            let lapse_interval_factor = 1.0 / interval_factor.sqrt();
-            let max_lapses = 8;
-            let seconds_per_new_card = 20.0 * 6.0;
-            let seconds_per_review_card = 20.0;
-            let seconds_per_lapsed_card = 20.0;
-            if use_analytical {
-                let mut anki = anki_sim_ana::AnkiSim::new()
-                    .with_interval_factor(interval_factor)
-                    .with_measured_retention_ratio(retention_ratio, 2.5)
-                    .with_lapse_interval_factor(lapse_interval_factor)
-                    .with_max_lapses(max_lapses)
-                    .with_seconds_per_new_card(seconds_per_new_card)
-                    .with_seconds_per_review_card(seconds_per_review_card)
-                    .with_seconds_per_lapsed_card(seconds_per_lapsed_card);
-
-                anki.simulate_n_days(365);
-                chart[y * width + x] = anki.cards_learned_per_hour();
-            } else {
-                let mut anki = anki_sim::AnkiSim::new()
-                    .with_interval_factor(interval_factor)
-                    .with_measured_retention_ratio(retention_ratio, 2.5)
-                    .with_lapse_interval_factor(lapse_interval_factor)
-                    .with_difficulty_variance(0.0)
-                    .with_max_lapses(max_lapses)
-                    .with_seconds_per_new_card(seconds_per_new_card)
-                    .with_seconds_per_review_card(seconds_per_review_card)
-                    .with_seconds_per_lapsed_card(seconds_per_lapsed_card);
-
-                anki.simulate_n_days(365, samples);
-                chart[y * width + x] = anki.cards_learned_per_hour();
-            }
-
-            print!(
-                "\r{:.1}%",
-                (x * height + y) as f32 / (height * width) as f32 * 100.0
+fn run_command() -> Result<(), String> {
+    let mut args = cli::Args::from_env()?;
+    args.load_scenario()?;
+    let mut params = Params::default();
+    args.apply_params(&mut params)?;
+
+    match args.command.as_str() {
+        "chart" => {
+            let path = args.get("output", "yar.png".to_string())?;
+            let options = chart::ChartOptions::from_args(&mut args, &params)?;
+            let grid = chart::Grid::from_args(&mut args, 65)?;
+            let data_path = args.take("data");
+            let sampling = sweep::Sampling::from_args(&mut args, 1)?;
+            args.finish()?;
+
+            let data = sweep::SweepData::run(&params, &grid.axes(), sampling);
+            if let Some(ref p) = data_path {
+                data.save(p)?;
+            }
+            let values = chart::chart_values(
+                &data.values(options.stat)?,
+                &grid,
+                &options.metric,
+                params.engine,
             );
-            let _ = std::io::stdout().flush();
+            chart::render_chart(&path, &values, &grid, &options)?;
         }
-    }
-    println!("\nDone.");
-
-    if use_analytical {
-        // Blur vertically to approximate interval variance.
-        let mut chart2 = vec![0.0f32; height * width];
-        for x in 0..width {
-            for y in 0..height {
-                let interval = interval_range.0 + (y as f32 * interval_step);
-                let blur_size = ((interval * 0.1) / interval_step) as usize;
-                let start = y - blur_size.min(y);
-                let end = (y + blur_size).min(height - 1);
-
-                chart2[y * width + x] = {
-                    let mut val = 0.0;
-                    for i in start..(end + 1) {
-                        val += chart[i * width + x];
+
+        "render" => {
+            let data_path = args
+                .take("data")
+                .ok_or_else(|| "render needs --data <file>".to_string())?;
+            let mut data = sweep::SweepData::load(&data_path)?;
+            if data.axes.len() == 2 && data.axes[0].name == "interval_factor" {
+                data = data.reorder(&[1, 0]);
+            }
+            let grid = chart::Grid::from_axes(&data.axes)?;
+            let path = args.get("output", "yar.png".to_string())?;
+            let options = chart::ChartOptions::from_args(&mut args, &data.params)?;
+            let interval_range = args.get_range("interval-range", grid.interval_range)?;
+            let retention_range = args.get_range("retention-range", grid.retention_range)?;
+            args.finish()?;
+
+            let values = chart::chart_values(
+                &data.values(options.stat)?,
+                &grid,
+                &options.metric,
+                data.params.engine,
+            );
+            let (values, grid) = grid.crop(&values, interval_range, retention_range)?;
+            chart::render_chart(&path, &values, &grid, &options)?;
+        }
+
+        "ridge" => {
+            let path = args.take("output");
+            let chart_path = args.take("chart");
+            let off = args.get("off", 10.0f32)? / 100.0;
+            let mut options = chart::ChartOptions::from_args(&mut args, &params)?;
+            options.ridge = true;
+            let grid = chart::Grid::from_args(&mut args, 65)?;
+            let data_path = args.take("data");
+            let sampling = sweep::Sampling::from_args(&mut args, 1)?;
+            args.finish()?;
+
+            let data = sweep::SweepData::run(&params, &grid.axes(), sampling);
+            if let Some(ref p) = data_path {
+                data.save(p)?;
+            }
+            let values =
+                chart::chart_values(&data.results, &grid, &options.metric, params.engine);
+            let ridge = ridge::extract(&values, &grid, off);
+            ridge::write_table(&mut create_output(&path)?, &ridge, &options.metric, off)
+                .map_err(|e| e.to_string())?;
+            if let Some(ref p) = chart_path {
+                chart::render_chart(p, &values, &grid, &options)?;
+            }
+        }
+
+        "slice" => {
+            let interval_range = args.get_range("interval-range", (2.0, 10.0))?;
+            let interval_cells = args.get("interval-cells", 33)?;
+            if interval_cells < 2 {
+                return Err("slices need at least 2 interval cells".to_string());
+            }
+            let retentions = match args.take("retentions") {
+                Some(list) => list
+                    .split(',')
+                    .map(|r| {
+                        r.trim()
+                            .parse::<f32>()
+                            .map_err(|_| format!("invalid retention '{}'", r))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![params.measured_retention.0],
+            };
+            let metric = args.get("metric", "cards_learned_per_hour".to_string())?;
+            if Metrics::default().get(&metric).is_none() {
+                return Err(format!("unknown metric '{}'", metric));
+            }
+            let plot_path = args.take("plot");
+            args.finish()?;
+
+            let series = print_vertical_slices(
+                &params,
+                &retentions,
+                interval_range,
+                interval_cells,
+                &metric,
+            );
+            if let Some(ref p) = plot_path {
+                let label = metric_label(&metric);
+                let title = format!("{} ({} engine)", label, params.engine.name());
+                svg::write_line_plot(p, &title, "Interval factor", &label, &series)
+                    .map_err(|e| format!("couldn't write '{}': {}", p, e))?;
+            }
+        }
+
+        "run" => {
+            let sampling = sweep::Sampling::from_args(&mut args, 1)?;
+            let revlog = args.take("revlog");
+            args.finish()?;
+            let runs = match revlog {
+                Some(ref p) => {
+                    if sampling.replicates > 1 {
+                        return Err("--revlog records a single run, so it can't have replicates"
+                            .to_string());
                     }
-                    val += chart[y * width + x] * ((blur_size * 2 + 1) - (end - start + 1)) as f32;
-                    val / (blur_size * 2 + 1) as f32
-                };
+                    collection::check_revlog_path(p)?;
+                    let (metrics, reviews) = params.simulate_revlog()?;
+                    collection::write_revlog(p, &reviews)?;
+                    vec![metrics]
+                }
+                None => sampling.simulate(&params),
+            };
+            params.print();
+            println!();
+            if runs.len() > 1 {
+                print_summaries(&runs);
+            } else {
+                print_metrics(&runs[0]);
             }
         }
-        chart = chart2;
-    }
 
-    if normalize_slices {
-        // Normalize each column (i.e. each interval within itself).
-        for x in 0..width {
-            let mut max = 0.0;
-            for y in 0..height {
-                max = if max > chart[y * width + x] {
-                    max
-                } else {
-                    chart[y * width + x]
-                };
-            }
-            for y in 0..height {
-                chart[y * width + x] /= max;
-            }
-        }
-    } else {
-        // Normalize across whole chart.
-        let max = chart
-            .iter()
-            .fold(0.0, |acc, n| if acc > *n { acc } else { *n });
-        for v in &mut chart {
-            *v /= max;
+        "daily" => {
+            let path = args.take("output");
+            let default_format = match path {
+                Some(ref p) => daily::Format::from_path(p),
+                None => daily::Format::Csv,
+            };
+            let format = args.get("format", default_format)?;
+            args.finish()?;
+
+            let records = params.simulate_daily();
+            daily::write_records(&mut create_output(&path)?, &records, format)
+                .map_err(|e| e.to_string())?;
+        }
+
+        "inspect" => {
+            let path = args.take("output");
+            let chart_path = args.take("chart");
+            let forecast_days = args.get("forecast", 30u32)?;
+            args.finish()?;
+
+            if let Some(ref deck) = params.starting_deck {
+                println!(
+                    "# Started from {} studied cards, leaving out {} new and {} suspended.",
+                    deck.cards.len(),
+                    deck.new_cards,
+                    deck.suspended
+                );
+                match deck.mean_ease() {
+                    Some(ease) => println!(
+                        "# Their mean ease is {:.2}; the simulation uses interval_factor.",
+                        ease
+                    ),
+                    None => println!("# None of them has an ease factor yet."),
+                }
+            }
+            let state = params.simulate_deck_state(forecast_days);
+            state.print();
+            if path.is_some() {
+                state
+                    .write_json(&mut create_output(&path)?)
+                    .map_err(|e| e.to_string())?;
+            }
+            if let Some(ref p) = chart_path {
+                let title = format!("Deck on day {}", state.day);
+                svg::write_bar_charts(p, &title, &state.bars())
+                    .map_err(|e| format!("couldn't write '{}': {}", p, e))?;
+            }
+        }
+
+        "calibrate" => {
+            let revlog = args
+                .take("revlog")
+                .ok_or_else(|| "calibrate needs --revlog <file>".to_string())?;
+            let fit = args.get("fit", calibrate::Fit::All)?;
+            let path = args.take("output");
+            args.finish()?;
+
+            let reviews = collection::load_revlog(&revlog)?;
+            if fit.retention() {
+                let retention = calibrate::fit_retention(&calibrate::observations(&reviews))
+                    .map_err(|e| format!("{}: {}", revlog, e))?;
+                print_retention_fit(&mut params, &retention, reviews.len());
+            }
+            if fit.times() {
+                if fit.retention() {
+                    println!();
+                }
+                let times =
+                    calibrate::fit_times(&reviews).map_err(|e| format!("{}: {}", revlog, e))?;
+                print_time_fit(&mut params, &times);
+            }
+            if let Some(ref p) = path {
+                File::create(p)
+                    .and_then(|mut f| params.write(&mut f))
+                    .map_err(|e| format!("couldn't write '{}': {}", p, e))?;
+            }
+        }
+
+        "lifetime" => {
+            let path = args.take("output");
+            let default_format = match path {
+                Some(ref p) => daily::Format::from_path(p),
+                None => daily::Format::Csv,
+            };
+            let format = args.get("format", default_format)?;
+            let settle = args.get("settle", 5.0f64)? / 100.0;
+            let max_days = args.get("max-days", 36500u32)?;
+            args.finish()?;
+
+            let lifetime = lifetime::simulate(&params, settle, max_days)?;
+            println!("deck_cards = {}", params.deck_cards.unwrap());
+            println!("introduction_days = {}", lifetime.introduction_days);
+            if lifetime.settled {
+                println!("settled_after_days = {}", lifetime.records.len());
+            } else {
+                println!("# Reviews hadn't settled after {} days.", max_days);
+            }
+            println!("new_hours = {}", lifetime.new_hours());
+            println!(
+                "introduction_review_hours = {}",
+                lifetime.introduction_review_hours()
+            );
+            println!("maintenance_hours = {}", lifetime.maintenance_hours());
+            println!("lifetime_hours = {}", lifetime.total_hours());
+            println!();
+            println!("{:<28}{:>14}{:>14}", "year after last new card", "reviews/day", "minutes/day");
+            for (year, (reviews, minutes)) in lifetime.yearly_load().iter().enumerate() {
+                println!("{:<28}{:>14.2}{:>14.2}", year + 1, reviews, minutes);
+            }
+            if path.is_some() {
+                daily::write_records(&mut create_output(&path)?, lifetime.maintenance(), format)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        "sweep" => {
+            let path = args.take("output");
+            let mut axes = args
+                .take_all("axis")
+                .iter()
+                .map(|spec| sweep::Axis::parse(spec))
+                .collect::<Result<Vec<_>, _>>()?;
+            if axes.is_empty() {
+                axes = chart::Grid::from_args(&mut args, 65)?.axes().to_vec();
+            }
+            let data_path = args.take("data");
+            let sampling = sweep::Sampling::from_args(&mut args, 1)?;
+            args.finish()?;
+
+            let data = sweep::SweepData::run(&params, &axes, sampling);
+            if let Some(ref p) = data_path {
+                data.save(p)?;
+            }
+            sweep::write_table(
+                &mut create_output(&path)?,
+                &axes,
+                &data.results,
+                data.std_errs.as_deref(),
+                data.samples.as_deref(),
+            )
+            .map_err(|e| e.to_string())?;
         }
-    }
 
-    // Create the image, enlarging by the magnification factor.
-    let magnification_factor = 10;
-    let image_width = (width - 1) * magnification_factor;
-    let image_height = (height - 1) * magnification_factor;
-    let width_map_fac = (width - 1) as f64 / (image_width - 1) as f64;
-    let height_map_fac = (height - 1) as f64 / (image_height - 1) as f64;
-    let mut image = vec![0u8; image_height * image_width * 4];
-    for x in 0..image_width {
-        for y in 0..image_height {
-            let val = {
-                let small_x = x as f64 * width_map_fac;
-                let small_y = y as f64 * height_map_fac;
-                let sx1 = small_x as usize;
-                let sy1 = small_y as usize;
-                let sx2 = (sx1 + 1).min(width - 1);
-                let sy2 = (sy1 + 1).min(height - 1);
-                let alpha_x = (small_x - small_x.floor()) as f32;
-                let alpha_y = (small_y - small_y.floor()) as f32;
-
-                let left = {
-                    let val1 = chart[sy1 * width + sx1];
-                    let val2 = chart[sy2 * width + sx1];
-                    (val1 * (1.0 - alpha_y)) + (val2 * alpha_y)
-                };
-
-                let right = {
-                    let val1 = chart[sy1 * width + sx2];
-                    let val2 = chart[sy2 * width + sx2];
-                    (val1 * (1.0 - alpha_y)) + (val2 * alpha_y)
-                };
-
-                let lerped = (left * (1.0 - alpha_x)) + (right * alpha_x);
-
-                (lerped * 255.0) as u8
+        "optimize" => {
+            let mut dims = args
+                .take_all("vary")
+                .iter()
+                .map(|spec| optimize::Dim::parse(spec))
+                .collect::<Result<Vec<_>, _>>()?;
+            if dims.is_empty() {
+                dims.push(optimize::Dim::parse("interval_factor=1.5,10")?);
+            }
+            let defaults = optimize::Settings::default();
+            let replicates = if params.engine == Engine::MonteCarlo {
+                8
+            } else {
+                defaults.sampling.replicates
+            };
+            let settings = optimize::Settings {
+                metric: args.get("metric", defaults.metric)?,
+                minimize: args.get("minimize", defaults.minimize)?,
+                sampling: sweep::Sampling::from_args(&mut args, replicates)?,
+                points: args.get("points", defaults.points)?,
+                rounds: args.get("rounds", defaults.rounds)?,
+                bootstrap: defaults.bootstrap,
+            };
+            args.finish()?;
+
+            let optimum = optimize::optimize(&params, &dims, &settings)?;
+            println!();
+            for (i, dim) in dims.iter().enumerate() {
+                println!(
+                    "{} = {:.4}  (95% CI {:.4} .. {:.4})",
+                    dim.name, optimum.values[i], optimum.ci[i].0, optimum.ci[i].1
+                );
+            }
+            println!(
+                "{} = {:.4} +/- {:.4} (standard error)",
+                settings.metric, optimum.metric_mean, optimum.metric_std_err
+            );
+        }
+
+        "plan" => {
+            let target = match args.take("target") {
+                Some(t) => t
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid value '{}' for --target", t))?,
+                None => params
+                    .target_known_cards
+                    .ok_or_else(|| "plan needs --target <known cards>".to_string())?,
             };
-            let i = y * image_width + x;
-            image[i * 4] = val;
-            image[i * 4 + 1] = val;
-            image[i * 4 + 2] = val;
-            image[i * 4 + 3] = 255;
+            let by = args.get("by", params.days)?;
+            let sampling = sweep::Sampling::from_args(&mut args, 1)?;
+            args.finish()?;
+
+            let plan = plan::new_cards_for_target(&params, target, by, &sampling)?;
+            println!("new_cards_per_day = {}", plan.new_cards_per_day);
+            println!("known_cards = {} (day {})", plan.known_cards, by);
+            println!(
+                "hours = {} ({:.2} per day)",
+                plan.hours,
+                plan.hours / by as f32
+            );
+        }
+
+        "compare" => {
+            args.finish()?;
+            let mut ana = params.clone();
+            ana.engine = Engine::Analytical;
+            let mut mc = params.clone();
+            mc.engine = Engine::MonteCarlo;
+            let ana_metrics = ana.simulate();
+            let mc_metrics = mc.simulate();
+            println!("{:<24}{:>14}{:>14}", "metric", "analytical", "monte-carlo");
+            for name in METRIC_NAMES {
+                println!(
+                    "{:<24}{:>14.4}{:>14.4}",
+                    name,
+                    ana_metrics.get(name).unwrap(),
+                    mc_metrics.get(name).unwrap(),
+                );
+            }
         }
+
+        "diff" => {
+            let a_path = args
+                .take("a")
+                .ok_or_else(|| "diff needs --a <file> and --b <file>".to_string())?;
+            let b_path = args
+                .take("b")
+                .ok_or_else(|| "diff needs --a <file> and --b <file>".to_string())?;
+            let kind = args.get("mode", diff::Kind::Difference)?;
+            let z = args.get("significance", 2.0f32)?;
+            let sampling = sweep::Sampling::from_args(&mut args, 4)?;
+            let path = args.get("output", "diff.png".to_string())?;
+            args.default_to("colormap", "diverging");
+            let a = diff::Side::load(&a_path, sampling)?;
+            let b = diff::Side::load(&b_path, sampling)?;
+            let metric = args.get("metric", "cards_learned_per_hour".to_string())?;
+            args.default_to("metric", &metric);
+            args.default_to(
+                "title",
+                &format!(
+                    "{}: {} vs {}",
+                    metric_label(&metric),
+                    b_path,
+                    a_path
+                ),
+            );
+            let mut options = chart::ChartOptions::from_args(&mut args, &a.data.params)?;
+            options.scaling.mode = match kind {
+                diff::Kind::Difference => scale::Mode::Difference,
+                diff::Kind::Ratio => scale::Mode::Ratio,
+            };
+            if options.scaling.log && kind == diff::Kind::Difference {
+                return Err("--log only works with --mode ratio".to_string());
+            }
+            args.finish()?;
+
+            let values = diff::compare(&a, &b, &metric, kind, z)?;
+            chart::render_chart(&path, &values, &a.grid, &options)?;
+        }
+
+        "help" | "--help" | "-h" => print!("{}", cli::USAGE),
+
+        cmd => {
+            return Err(format!(
+                "unknown command '{}'\n\n{}",
+                cmd,
+                cli::USAGE
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens the given file for writing, or stdout if there's no path.
+fn create_output(path: &Option<String>) -> Result<Box<dyn Write>, String> {
+    Ok(match *path {
+        Some(ref p) => {
+            Box::new(File::create(p).map_err(|e| format!("couldn't create '{}': {}", p, e))?)
+        }
+        None => Box::new(std::io::stdout()),
+    })
+}
+
+fn print_metrics(metrics: &Metrics) {
+    for name in METRIC_NAMES {
+        println!("{} = {}", name, metrics.get(name).unwrap());
+    }
+}
+
+/// Prints each metric's mean, standard error and spread across replicate
+/// runs.
+fn print_summaries(runs: &[Metrics]) {
+    println!(
+        "{:<24}{:>14}{:>14}{:>14}{:>14}{:>14}",
+        "metric", "mean", "std_err", "p5", "p50", "p95"
+    );
+    for (name, s) in METRIC_NAMES.iter().zip(stats::summarize_metrics(runs)) {
+        println!(
+            "{:<24}{:>14.4}{:>14.4}{:>14.4}{:>14.4}{:>14.4}",
+            name, s.mean, s.std_err, s.p5, s.p50, s.p95
+        );
     }
+    println!("({} replicates)", runs.len());
+}
 
-    // Write the image
-    png_encode_mini::write_rgba_from_u8(
-        &mut File::create(path).unwrap(),
-        &image,
-        image_width as u32,
-        image_height as u32,
-    ).unwrap();
+/// Sets the measured retention from a fitted forgetting curve and prints
+/// the fit with a calibration table.
+fn print_retention_fit(params: &mut Params, fit: &calibrate::RetentionFit, entries: usize) {
+    let factor = params.measured_retention.1 as f64;
+    params.measured_retention.0 = fit.retention(factor) as f32;
+    let ci = fit.retention_ci(factor);
+    println!(
+        "# {} reviews after a successful review, {:.1}% recalled at a mean interval factor of {:.2}.",
+        fit.observations,
+        fit.observed_retention * 100.0,
+        fit.mean_factor
+    );
+    println!(
+        "# The other {} of {} revlog entries were left out: learning and relearning steps, first reviews, \
+         reviews after a lapse and reviews within half a day of the last.",
+        entries - fit.observations,
+        entries
+    );
+    println!(
+        "measured_retention = {:.4}  (95% CI {:.4} .. {:.4})",
+        params.measured_retention.0, ci.0, ci.1
+    );
+    println!("measured_retention_interval = {}", factor);
+    println!(
+        "forgetting_rate = {:.5} +/- {:.5} (standard error)",
+        fit.rate, fit.rate_std_err
+    );
+    println!("log_likelihood = {:.2}", fit.log_likelihood);
+    println!("null_log_likelihood = {:.2}", fit.null_log_likelihood);
+    println!("brier_score = {:.4}", fit.brier_score);
+    println!(
+        "hosmer_lemeshow = {:.2} ({} degrees of freedom)",
+        fit.chi_squared, fit.degrees_of_freedom
+    );
+    println!();
+    println!(
+        "{:<20}{:>10}{:>12}{:>12}",
+        "interval factor", "reviews", "recalled", "predicted"
+    );
+    for bin in &fit.bins {
+        println!(
+            "{:<20}{:>10}{:>11.1}%{:>11.1}%",
+            format!("{:.2}-{:.2}", bin.min_factor, bin.max_factor),
+            bin.count,
+            bin.observed * 100.0,
+            bin.predicted * 100.0
+        );
+    }
 }
 
-fn print_vertical_slice(
-    samples: u32,
+/// Sets the time costs fitted to answer durations and prints them with
+/// their spread and how they vary with the interval.
+fn print_time_fit(params: &mut Params, fit: &calibrate::TimeFit) {
+    let costs = [
+        ("seconds_per_new_card", "new cards", &fit.new_card, fit.new_cards),
+        ("seconds_per_review_card", "reviews", &fit.review_card, fit.reviews),
+        ("seconds_per_lapsed_card", "lapses", &fit.lapsed_card, fit.lapses),
+    ];
+    for &(name, what, summary, count) in &costs {
+        match *summary {
+            Some(s) => {
+                params.set_value(name, s.mean).unwrap();
+                println!(
+                    "{} = {:.1}  ({} {}, median {:.1}, 5-95% {:.1} .. {:.1})",
+                    name, s.mean, count, what, s.p50, s.p5, s.p95
+                );
+            }
+            None => println!("# No {} to time, so {} is unchanged.", what, name),
+        }
+    }
+    println!();
+    println!(
+        "{:<16}{:>10}{:>10}{:>12}{:>12}{:>12}",
+        "interval", "reviews", "lapses", "recalled_s", "forgotten_s", "relearn_s"
+    );
+    for (i, bin) in fit.by_interval.iter().enumerate() {
+        if bin.reviews == 0 {
+            continue;
+        }
+        println!(
+            "{:<16}{:>10}{:>10}{:>12.1}{:>12.1}{:>12.1}",
+            deck_state::interval_label(i),
+            bin.reviews,
+            bin.lapses,
+            bin.recalled_seconds,
+            bin.forgotten_seconds,
+            bin.relearning_seconds
+        );
+    }
+}
+
+/// Prints metrics across interval factors for each measured retention, and
+/// returns `metric` along each slice for plotting.
+fn print_vertical_slices(
+    base: &Params,
+    retentions: &[f32],
     interval_range: (f32, f32),
     interval_cells: u32,
-    measured_retention: f32,
-) {
+    metric: &str,
+) -> Vec<svg::Series> {
     let count = interval_cells as usize;
     let interval_step = (interval_range.1 - interval_range.0) / (interval_cells - 1) as f32;
 
-    for n in 0..count {
-        let interval_factor = interval_range.0 + (interval_step * n as f32);
-        let mut anki = anki_sim::AnkiSim::new()
-            .with_interval_factor(interval_factor)
-            .with_measured_retention_ratio(measured_retention, 2.5)
-            .with_lapse_interval_factor(1.0 / interval_factor.sqrt())
-            .with_difficulty_variance(0.00)
-            .with_max_lapses(8)
-            .with_seconds_per_new_card(20.0 * 6.0)
-            .with_seconds_per_review_card(20.0)
-            .with_seconds_per_lapsed_card(20.0);
-
-        anki.simulate_n_days(365, samples);
+    let mut series = Vec::new();
+    for &retention in retentions {
+        if retentions.len() > 1 {
+            println!("Measured retention: {}", retention);
+        }
+        let mut points = Vec::with_capacity(count);
+        for n in 0..count {
+            let mut params = base.clone();
+            params.measured_retention.0 = retention;
+            params.interval_factor = interval_range.0 + (interval_step * n as f32);
+            let metrics = params.simulate();
 
-        println!(
-            "Interval Factor: {:.2}  |  Cards learned per hour: {:.2}  |  Lapse ratio: {:.2}",
-            interval_factor,
-            anki.cards_learned_per_hour(),
-            anki.lapses_per_review(),
-        );
+            println!(
+                "Interval Factor: {:.2}  |  Cards learned per hour: {:.2}  |  Lapse ratio: {:.2}",
+                params.interval_factor, metrics.cards_learned_per_hour, metrics.lapses_per_review,
+            );
+            points.push((params.interval_factor, metrics.get(metric).unwrap()));
+        }
+        series.push(svg::Series {
+            label: format!("retention {}", retention),
+            points,
+        });
     }
+    series
 }
@@ -0,0 +1,440 @@
+//! Derivative-free optimization of simulation parameters.
+//!
+//! Uses coordinate-wise response-surface search: each round samples a
+//! bracket around the current best value of one parameter, fits a quadratic
+//! to the (replicate-averaged) metric by least squares, and moves to the
+//! fitted peak before narrowing the bracket.  Fitting a curve through many
+//! noisy points rather than comparing individual points is what makes this
+//! robust to Monte Carlo noise.  Confidence intervals come from a residual
+//! bootstrap of the final fits.
+
+use rand::{self, random, Rng};
+
+use anki_sim;
+use params::{Metrics, Params, INTEGER_PARAMS};
+use stats::{mean, percentile, std_err};
+use sweep::Sampling;
+
+/// A parameter to optimize and the bounds to search within.
+#[derive(Debug, Clone)]
+pub struct Dim {
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Dim {
+    /// Parses `name=min,max`.
+    pub fn parse(spec: &str) -> Result<Dim, String> {
+        let err = || {
+            format!(
+                "invalid parameter bounds '{}' (expected e.g. 'interval_factor=1.5,10')",
+                spec
+            )
+        };
+        let eq = spec.find('=').ok_or_else(err)?;
+        let name = spec[..eq].trim().replace('-', "_");
+        let (min, max) = ::cli::parse_range(&spec[(eq + 1)..]).map_err(|_| err())?;
+        if min >= max {
+            return Err(err());
+        }
+        Params::default().set_value(&name, min as f64)?;
+        Ok(Dim { name, min, max })
+    }
+
+    fn is_integer(&self) -> bool {
+        INTEGER_PARAMS.contains(&self.name.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub metric: String,
+    pub minimize: bool,
+    pub sampling: Sampling, // Simulations averaged per sample point.
+    pub points: usize,      // Sample points per bracket.
+    pub rounds: u32,
+    pub bootstrap: u32, // Bootstrap resamples for the confidence intervals.
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            metric: "cards_learned_per_hour".to_string(),
+            minimize: false,
+            sampling: Sampling::fixed(1),
+            points: 9,
+            rounds: 4,
+            bootstrap: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Optimum {
+    pub values: Vec<f32>,
+    pub ci: Vec<(f32, f32)>, // 95% confidence interval of each value.
+    pub metric_mean: f32,
+    pub metric_std_err: f32,
+}
+
+pub fn optimize(base: &Params, dims: &[Dim], settings: &Settings) -> Result<Optimum, String> {
+    if Metrics::default().get(&settings.metric).is_none() {
+        return Err(format!("unknown metric '{}'", settings.metric));
+    }
+    let points = settings.points.max(3);
+
+    // Sample points share one seed for common random numbers.  Otherwise a
+    // fixed seed still gives each point its own, as sweeps do.
+    let mut base = base.clone();
+    if settings.sampling.common && base.seed.is_none() {
+        base.seed = Some(random::<u64>() >> 11);
+    }
+    let mut evaluated = 0;
+    let mut sample = |values: &[f32], sampling: &Sampling| {
+        evaluated += 1;
+        evaluate(&base, dims, values, settings, sampling, evaluated)
+    };
+
+    let mut best: Vec<f32> = dims.iter().map(|d| (d.min + d.max) * 0.5).collect();
+    let mut brackets: Vec<(f32, f32)> = dims.iter().map(|d| (d.min, d.max)).collect();
+    let mut last_fits: Vec<Option<(Vec<f64>, Vec<f64>)>> = vec![None; dims.len()];
+
+    for round in 0..settings.rounds.max(1) {
+        for (d, dim) in dims.iter().enumerate() {
+            let (lo, hi) = brackets[d];
+
+            // Sample the bracket.
+            let mut xs: Vec<f64> = (0..points)
+                .map(|i| lo as f64 + (hi - lo) as f64 * i as f64 / (points - 1) as f64)
+                .collect();
+            if dim.is_integer() {
+                for x in &mut xs {
+                    *x = x.round();
+                }
+                xs.dedup();
+            }
+            // Points where the metric isn't finite, e.g. cards learned per
+            // hour with no time spent, can't be fitted.
+            let (xs, ys): (Vec<f64>, Vec<f64>) = xs
+                .iter()
+                .map(|&x| {
+                    let mut values = best.clone();
+                    values[d] = x as f32;
+                    (x, mean(&sample(&values, &settings.sampling)))
+                })
+                .filter(|&(_, y)| y.is_finite())
+                .unzip();
+            if xs.is_empty() {
+                return Err(format!(
+                    "{} isn't finite anywhere with {} from {} to {}",
+                    settings.metric, dim.name, lo, hi
+                ));
+            }
+
+            let x_best = peak(&xs, &ys, lo as f64, hi as f64);
+            best[d] = if dim.is_integer() {
+                x_best.round() as f32
+            } else {
+                x_best as f32
+            };
+
+            // Narrow the bracket around the new best value.
+            let half = (hi - lo) * 0.25;
+            let (mut lo, mut hi) = (best[d] - half, best[d] + half);
+            if lo < dim.min {
+                hi += dim.min - lo;
+                lo = dim.min;
+            }
+            if hi > dim.max {
+                lo = (lo - (hi - dim.max)).max(dim.min);
+                hi = dim.max;
+            }
+            brackets[d] = (lo, hi);
+            last_fits[d] = Some((xs, ys));
+
+            eprintln!("Round {}: {} = {:.4}", round + 1, dim.name, best[d]);
+        }
+    }
+
+    // Bootstrap confidence intervals from the final fit of each dimension.
+    let mut rng = rand::thread_rng();
+    let mut ci = Vec::new();
+    for (d, dim) in dims.iter().enumerate() {
+        let (ref xs, ref ys) = *last_fits[d].as_ref().unwrap();
+        let (lo, hi) = (
+            xs.iter().cloned().fold(f64::INFINITY, f64::min),
+            xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        );
+        let interval = match fit_quadratic(xs, ys) {
+            Some(coef) if settings.bootstrap > 0 => {
+                let fitted: Vec<f64> = xs.iter().map(|&x| eval_quadratic(&coef, x)).collect();
+                let residuals: Vec<f64> = ys.iter().zip(&fitted).map(|(y, f)| y - f).collect();
+                let mut peaks: Vec<f64> = (0..settings.bootstrap)
+                    .map(|_| {
+                        let resampled: Vec<f64> = fitted
+                            .iter()
+                            .map(|f| f + residuals[rng.gen_range(0, residuals.len())])
+                            .collect();
+                        peak(xs, &resampled, lo, hi)
+                    })
+                    .filter(|p| p.is_finite())
+                    .collect();
+                peaks.sort_by(f64::total_cmp);
+                if peaks.is_empty() {
+                    (best[d] as f64, best[d] as f64)
+                } else {
+                    (percentile(&peaks, 0.025), percentile(&peaks, 0.975))
+                }
+            }
+            _ => (best[d] as f64, best[d] as f64),
+        };
+        ci.push(if dim.is_integer() {
+            (interval.0.round() as f32, interval.1.round() as f32)
+        } else {
+            (interval.0 as f32, interval.1 as f32)
+        });
+    }
+
+    // Final evaluation at the optimum.
+    let sampling = Sampling {
+        replicates: settings.sampling.replicates.max(2),
+        max_replicates: settings.sampling.max_replicates.max(2),
+        ..settings.sampling
+    };
+    let samples = sample(&best, &sampling);
+    if samples.iter().any(|s| !s.is_finite()) {
+        return Err(format!("{} isn't finite at the optimum", settings.metric));
+    }
+    let sign = if settings.minimize { -1.0 } else { 1.0 };
+    Ok(Optimum {
+        values: best,
+        ci,
+        metric_mean: (mean(&samples) * sign) as f32,
+        metric_std_err: std_err(&samples) as f32,
+    })
+}
+
+/// Simulates the `point`th sample, with `values` applied, as `sampling`
+/// calls for, returning the metric of each run, negated when minimizing.
+fn evaluate(
+    base: &Params,
+    dims: &[Dim],
+    values: &[f32],
+    settings: &Settings,
+    sampling: &Sampling,
+    point: u64,
+) -> Vec<f64> {
+    let mut params = base.clone();
+    for (dim, &v) in dims.iter().zip(values) {
+        params.set_value(&dim.name, v as f64).unwrap();
+    }
+    if !sampling.common {
+        params.seed = base.seed.map(|seed| anki_sim::mix_seed(seed, point));
+    }
+    let sign = if settings.minimize { -1.0 } else { 1.0 };
+    sampling
+        .simulate(&params)
+        .iter()
+        .map(|m| m.get(&settings.metric).unwrap() as f64 * sign)
+        .collect()
+}
+
+/// The location of the maximum of `ys`, which must be finite, refined by a quadratic fit when
+/// the fit has an interior peak within `[lo, hi]`.
+fn peak(xs: &[f64], ys: &[f64], lo: f64, hi: f64) -> f64 {
+    let mut best_i = 0;
+    for i in 1..ys.len() {
+        if ys[i] > ys[best_i] {
+            best_i = i;
+        }
+    }
+
+    match fit_quadratic(xs, ys) {
+        Some(c) if c[2] < 0.0 => {
+            let x = -c[1] / (2.0 * c[2]);
+            if x >= lo && x <= hi {
+                x
+            } else {
+                xs[best_i]
+            }
+        }
+        _ => xs[best_i],
+    }
+}
+
+/// Least-squares fit of `y = c0 + c1 x + c2 x^2`.
+fn fit_quadratic(xs: &[f64], ys: &[f64]) -> Option<[f64; 3]> {
+    if xs.len() < 3 || ys.iter().any(|y| !y.is_finite()) {
+        return None;
+    }
+
+    // Center and scale x for conditioning.
+    let center = mean(xs);
+    let scale = xs
+        .iter()
+        .map(|x| (x - center).abs())
+        .fold(0.0, f64::max)
+        .max(1.0e-12);
+
+    // Normal equations.
+    let mut m = [[0.0f64; 4]; 3];
+    for (&x, &y) in xs.iter().zip(ys) {
+        let t = (x - center) / scale;
+        let row = [1.0, t, t * t];
+        for i in 0..3 {
+            for j in 0..3 {
+                m[i][j] += row[i] * row[j];
+            }
+            m[i][3] += row[i] * y;
+        }
+    }
+
+    // Gaussian elimination with partial pivoting.
+    for col in 0..3 {
+        let pivot = (col..3)
+            .max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))
+            .unwrap();
+        if m[pivot][col].abs() < 1.0e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        let pivot_row = m[col];
+        for (row_i, row) in m.iter_mut().enumerate() {
+            if row_i != col {
+                let f = row[col] / pivot_row[col];
+                for (v, p) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                    *v -= f * p;
+                }
+            }
+        }
+    }
+    let (a, b, c) = (m[0][3] / m[0][0], m[1][3] / m[1][1], m[2][3] / m[2][2]);
+
+    // Convert back to unscaled x.
+    let c2 = c / (scale * scale);
+    let c1 = b / scale - 2.0 * c2 * center;
+    let c0 = a - b * center / scale + c2 * center * center;
+    Some([c0, c1, c2])
+}
+
+fn eval_quadratic(c: &[f64; 3], x: f64) -> f64 {
+    c[0] + c[1] * x + c[2] * x * x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use params::Engine;
+
+    #[test]
+    fn fits_quadratics_exactly() {
+        let xs: Vec<f64> = (0..7).map(|i| 100.0 + i as f64 * 10.0).collect();
+        let ys: Vec<f64> = xs.iter().map(|x| 3.0 - 0.5 * x + 0.002 * x * x).collect();
+        let c = fit_quadratic(&xs, &ys).unwrap();
+        for (&x, &y) in xs.iter().zip(&ys) {
+            assert!((eval_quadratic(&c, x) - y).abs() < 1e-9);
+        }
+        assert!((c[2] - 0.002).abs() < 1e-12);
+    }
+
+    #[test]
+    fn degenerate_fits_fail() {
+        assert!(fit_quadratic(&[1.0, 2.0], &[1.0, 2.0]).is_none());
+        assert!(fit_quadratic(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0]).is_none());
+        assert!(fit_quadratic(&[1.0, 2.0, 3.0], &[1.0, f64::NAN, 3.0]).is_none());
+    }
+
+    #[test]
+    fn finds_peaks() {
+        let xs: Vec<f64> = (0..9).map(|i| i as f64).collect();
+        // A noisy parabola peaking at 3.
+        let noise = [0.1, -0.2, 0.05, 0.0, -0.1, 0.15, -0.05, 0.1, -0.1];
+        let ys: Vec<f64> = xs
+            .iter()
+            .zip(&noise)
+            .map(|(x, n)| 10.0 - (x - 3.0) * (x - 3.0) + n)
+            .collect();
+        assert!((peak(&xs, &ys, 0.0, 8.0) - 3.0).abs() < 0.1);
+        // Monotonic data peaks at the edge.
+        let ys: Vec<f64> = xs.iter().map(|x| x * x).collect();
+        assert_eq!(peak(&xs, &ys, 0.0, 8.0), 8.0);
+    }
+
+    #[test]
+    fn parses_bounds() {
+        let dim = Dim::parse("interval-factor=1.5,10").unwrap();
+        assert_eq!(
+            (dim.name.as_str(), dim.min, dim.max),
+            ("interval_factor", 1.5, 10.0)
+        );
+        assert!(Dim::parse("interval_factor=10,1.5").is_err());
+        assert!(Dim::parse("bogus=1,2").is_err());
+        assert!(Dim::parse("interval_factor").is_err());
+    }
+
+    #[test]
+    fn optimizes_the_analytical_engine() {
+        let base = Params {
+            engine: Engine::Analytical,
+            days: 200,
+            ..Params::default()
+        };
+        let dims = [Dim::parse("interval_factor=1.5,10").unwrap()];
+        let settings = Settings {
+            bootstrap: 100,
+            ..Settings::default()
+        };
+        let optimum = optimize(&base, &dims, &settings).unwrap();
+        let (value, (lo, hi)) = (optimum.values[0], optimum.ci[0]);
+        assert!(value > 1.5 && value < 10.0);
+        // The engine is deterministic, so the bootstrap has little noise to
+        // resample and its interval hugs the optimum.
+        assert!(lo <= hi && hi - lo < 0.5, "{} to {}", lo, hi);
+        assert!((lo - 0.1..hi + 0.1).contains(&value));
+        assert_eq!(optimum.metric_std_err, 0.0);
+
+        // Every sample is better than the same metric at the edges.
+        let at = |x: f32| {
+            let mut params = base.clone();
+            params.set_value("interval_factor", x as f64).unwrap();
+            params.simulate().cards_learned_per_hour
+        };
+        assert!(optimum.metric_mean >= at(1.5).max(at(10.0)));
+    }
+
+    #[test]
+    fn seeded_replicates_differ_but_repeat() {
+        let base = Params {
+            engine: Engine::MonteCarlo,
+            days: 60,
+            new_cards_per_day: 50,
+            seed: Some(3),
+            ..Params::default()
+        };
+        let dims = [Dim::parse("interval_factor=1.5,10").unwrap()];
+        let settings = Settings {
+            sampling: Sampling::fixed(2),
+            points: 3,
+            rounds: 1,
+            bootstrap: 0,
+            ..Settings::default()
+        };
+        let optimum = optimize(&base, &dims, &settings).unwrap();
+        assert!(optimum.metric_std_err > 0.0);
+        let again = optimize(&base, &dims, &settings).unwrap();
+        assert_eq!(again.values, optimum.values);
+        assert_eq!(again.metric_mean, optimum.metric_mean);
+    }
+
+    #[test]
+    fn non_finite_metrics_are_errors() {
+        let base = Params {
+            days: 5,
+            ..Params::default()
+        };
+        // No new cards means no time spent, so no cards learned per hour.
+        let dims = [Dim::parse("new_cards_per_day=0,0.4").unwrap()];
+        assert!(optimize(&base, &dims, &Settings::default()).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Working back from a goal: the new cards per day needed to know a target
+//! number of cards by a given day.
+
+use params::{Engine, Params};
+use stats::mean;
+use sweep::Sampling;
+
+/// The cheapest schedule that reaches a target.
+#[derive(Debug, Copy, Clone)]
+pub struct Plan {
+    pub new_cards_per_day: u32,
+    /// Known cards at the deadline with this many new cards per day.
+    pub known_cards: f32,
+    /// Hours spent on new cards and reviews by the deadline.
+    pub hours: f32,
+}
+
+/// The fewest new cards per day with which `target` cards are known at the
+/// end of day `by`.  Monte Carlo runs are averaged over the replicates of
+/// `sampling`, and every run of the search shares the same random numbers
+/// so that noise can't make it look non-monotonic.
+pub fn new_cards_for_target(
+    base: &Params,
+    target: f32,
+    by: u32,
+    sampling: &Sampling,
+) -> Result<Plan, String> {
+    if by == 0 {
+        return Err("the deadline must be at least one day".to_string());
+    }
+    // A finite deck can't be known beyond its own cards and the ones it
+    // started with, however fast they're added.
+    let starting = base.starting_deck.as_ref().map_or(0, |d| d.cards.len());
+    if let Some(cards) = base.deck_cards {
+        let limit = cards as usize + starting;
+        if target > limit as f32 {
+            return Err(format!(
+                "{} known cards can't be reached with a deck of {} cards",
+                target, limit
+            ));
+        }
+    }
+    let mut params = base.clone();
+    params.days = by;
+    params.target_known_cards = None;
+    if params.seed.is_none() {
+        params.seed = Some(::rand::random::<u64>() >> 11);
+    }
+
+    let evaluate = |new_cards_per_day: u32| {
+        let mut params = params.clone();
+        params.new_cards_per_day = new_cards_per_day;
+        let runs = sampling.simulate(&params);
+        let known: Vec<f64> = runs.iter().map(|m| m.known_cards as f64).collect();
+        let hours: Vec<f64> = runs
+            .iter()
+            .map(|m| (m.new_time + m.review_time) as f64)
+            .collect();
+        Plan {
+            new_cards_per_day,
+            known_cards: mean(&known) as f32,
+            hours: mean(&hours) as f32,
+        }
+    };
+
+    let none = evaluate(0);
+    if none.known_cards >= target {
+        return Ok(none);
+    }
+
+    // Known cards grow in proportion to the new cards per day, so the
+    // analytical engine's count for one a day bounds the search, with room
+    // to spare for Monte Carlo noise.  Adding a finite deck faster than all
+    // of it on the first day changes nothing.
+    let mut analytical = params.clone();
+    analytical.engine = Engine::Analytical;
+    analytical.new_cards_per_day = 0;
+    let known_before = analytical.simulate().known_cards;
+    analytical.new_cards_per_day = 1;
+    let known_per_card = analytical.simulate().known_cards - known_before;
+    let unreachable = || format!("{} known cards can't be reached by day {}", target, by);
+    if known_per_card <= 0.0 {
+        return Err(unreachable());
+    }
+    let mut max_new_cards = (2.0 * (target - known_before) / known_per_card)
+        .ceil()
+        .clamp(1.0, u32::MAX as f32) as u32;
+    if let Some(cards) = base.deck_cards {
+        max_new_cards = max_new_cards.min(cards.max(1));
+    }
+
+    // Double until the target is reached, then bisect.
+    let (mut lo, mut hi) = (0, 1);
+    let mut best = evaluate(hi);
+    while best.known_cards < target {
+        if hi >= max_new_cards {
+            return Err(unreachable());
+        }
+        lo = hi;
+        hi = (hi * 2).min(max_new_cards);
+        best = evaluate(hi);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        let plan = evaluate(mid);
+        if plan.known_cards >= target {
+            hi = mid;
+            best = plan;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Params {
+        Params {
+            engine: Engine::Analytical,
+            ..Params::default()
+        }
+    }
+
+    #[test]
+    fn finds_the_fewest_new_cards() {
+        let sampling = Sampling::fixed(1);
+        let plan = new_cards_for_target(&params(), 500.0, 100, &sampling).unwrap();
+        assert!(plan.known_cards >= 500.0);
+        let mut fewer = params();
+        fewer.days = 100;
+        fewer.new_cards_per_day = plan.new_cards_per_day - 1;
+        assert!(fewer.simulate().known_cards < 500.0);
+    }
+
+    #[test]
+    fn small_decks_fail_without_searching() {
+        let small = Params {
+            deck_cards: Some(100),
+            ..params()
+        };
+        let sampling = Sampling::fixed(1);
+        let err = new_cards_for_target(&small, 200.0, 30, &sampling).unwrap_err();
+        assert!(err.contains("deck of 100 cards"), "{}", err);
+        // Knowing every card of the deck takes more than the deck has.
+        assert!(new_cards_for_target(&small, 100.0, 30, &sampling).is_err());
+        assert!(new_cards_for_target(&small, 50.0, 30, &sampling).is_ok());
+    }
+
+    #[test]
+    fn targets_bound_the_search() {
+        let sampling = Sampling::fixed(1);
+        let plan = new_cards_for_target(&params(), 0.0, 30, &sampling).unwrap();
+        assert_eq!(plan.new_cards_per_day, 0);
+        let plan = new_cards_for_target(&params(), 450.0, 1, &sampling).unwrap();
+        assert_eq!(plan.new_cards_per_day, 500);
+        // A finite deck is also a bound.
+        let small = Params {
+            deck_cards: Some(100),
+            ..params()
+        };
+        let err = new_cards_for_target(&small, 95.0, 1, &sampling).unwrap_err();
+        assert!(err.contains("can't be reached by day 1"), "{}", err);
+
+        let monte_carlo = Params {
+            engine: Engine::MonteCarlo,
+            seed: Some(1),
+            ..params()
+        };
+        let plan = new_cards_for_target(&monte_carlo, 36.0, 1, &sampling).unwrap();
+        assert!(plan.new_cards_per_day >= 36 && plan.new_cards_per_day <= 80);
+        assert!(new_cards_for_target(&monte_carlo, 0.0, 1, &sampling).is_ok());
+    }
+}
@@ -0,0 +1,147 @@
+//! The ridge of a chart: the most efficient interval factor for each
+//! measured retention.
+
+use std::io::{self, Write};
+
+use chart::Grid;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RidgePoint {
+    pub retention: f32,
+    pub interval_factor: f32,
+    pub value: f32, // Metric value at the optimum.
+    /// Fractional efficiency lost by using an interval factor `off` below
+    /// or above the optimum.  NaN where that falls outside the grid.
+    pub loss_below: f32,
+    pub loss_above: f32,
+}
+
+/// Finds the optimal interval factor of each column of `chart` (row-major,
+/// `y * width + x`), refined between grid points by fitting a parabola
+/// through the best cell and its neighbors.
+///
+/// `off` is the fractional distance from the optimum (e.g. 0.1 for ±10%)
+/// at which to report the efficiency loss.
+pub fn extract(chart: &[f32], grid: &Grid, off: f32) -> Vec<RidgePoint> {
+    let height = grid.interval_cells as usize;
+    let width = grid.retention_cells as usize;
+    let step = grid.interval_step();
+
+    (0..width)
+        .map(|x| {
+            let column: Vec<f32> = (0..height).map(|y| chart[y * width + x]).collect();
+
+            let mut best = 0;
+            for y in 1..height {
+                if column[y] > column[best] {
+                    best = y;
+                }
+            }
+
+            let (offset, value) = if best > 0 && best < height - 1 {
+                let (a, b, c) = (column[best - 1], column[best], column[best + 1]);
+                let denom = a - (2.0 * b) + c;
+                if denom < 0.0 {
+                    let offset = (0.5 * (a - c) / denom).clamp(-0.5, 0.5);
+                    (offset, b - (0.25 * (a - c) * offset))
+                } else {
+                    (0.0, b)
+                }
+            } else {
+                (0.0, column[best])
+            };
+            let factor = grid.interval_factor(best) + (offset * step);
+
+            let loss = |f: f32| {
+                let pos = (f - grid.interval_range.0) / step;
+                if pos < 0.0 || pos > (height - 1) as f32 || value <= 0.0 {
+                    return f32::NAN;
+                }
+                let y1 = pos as usize;
+                let y2 = (y1 + 1).min(height - 1);
+                let alpha = pos - y1 as f32;
+                let v = (column[y1] * (1.0 - alpha)) + (column[y2] * alpha);
+                (1.0 - (v / value)).max(0.0)
+            };
+
+            RidgePoint {
+                retention: grid.retention(x),
+                interval_factor: factor,
+                value,
+                loss_below: loss(factor * (1.0 - off)),
+                loss_above: loss(factor * (1.0 + off)),
+            }
+        })
+        .collect()
+}
+
+/// Writes the ridge as a tab-separated table, with `metric` naming the
+/// value column.
+pub fn write_table<W: Write>(
+    out: &mut W,
+    ridge: &[RidgePoint],
+    metric: &str,
+    off: f32,
+) -> io::Result<()> {
+    let pct = off * 100.0;
+    writeln!(
+        out,
+        "measured_retention\tinterval_factor\t{}\tloss_at_minus_{}pct\tloss_at_plus_{}pct",
+        metric, pct, pct
+    )?;
+    for p in ridge {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            p.retention, p.interval_factor, p.value, p.loss_below, p.loss_above
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Grid {
+        Grid {
+            interval_range: (1.0, 9.0),
+            interval_cells: 9,
+            retention_range: (0.5, 0.9),
+            retention_cells: 2,
+        }
+    }
+
+    /// A chart whose columns are parabolas peaking at `peaks`.
+    fn chart(grid: &Grid, peaks: &[f32]) -> Vec<f32> {
+        let mut chart = Vec::new();
+        for y in 0..grid.interval_cells {
+            let f = grid.interval_factor(y as usize);
+            for p in peaks {
+                chart.push(100.0 - (f - p) * (f - p));
+            }
+        }
+        chart
+    }
+
+    #[test]
+    fn finds_peaks_between_cells() {
+        let grid = grid();
+        let ridge = extract(&chart(&grid, &[4.3, 6.0]), &grid, 0.1);
+        assert!((ridge[0].interval_factor - 4.3).abs() < 1e-4);
+        assert!((ridge[0].value - 100.0).abs() < 1e-3);
+        assert_eq!(ridge[1].interval_factor, 6.0);
+        assert_eq!(ridge[1].retention, 0.9);
+    }
+
+    #[test]
+    fn reports_losses_off_the_peak() {
+        let grid = grid();
+        let ridge = extract(&chart(&grid, &[5.0, 8.9]), &grid, 0.1);
+        // 10% below 5 is 4.5, halfway between cells of 99 and 100.
+        assert!((ridge[0].loss_below - 0.005).abs() < 1e-6);
+        assert!(ridge[0].loss_above > 0.0);
+        // 10% above a peak near the edge falls off the grid.
+        assert!(ridge[1].loss_above.is_nan());
+    }
+}
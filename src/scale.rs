@@ -0,0 +1,357 @@
+//! How chart values are normalized and mapped onto the color map.
+
+use std::str::FromStr;
+
+use chart::{format_tick, nice_ticks, Grid};
+
+/// What the colors of a chart show.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// Percent of the best cell in the same column (measured retention).
+    Columns,
+    /// Percent of the best cell in the same row (interval factor).
+    Rows,
+    /// Percent of the best cell in the whole chart.
+    Global,
+    /// Percent lost relative to the best cell in the same column.
+    Loss,
+    /// The metric's own values.
+    Absolute,
+    /// Differences between two charts, centered on zero.  Set by `diff`
+    /// rather than `--scale`.
+    Difference,
+    /// Ratios between two charts, centered on one.  Set by `diff` rather
+    /// than `--scale`.
+    Ratio,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        match s {
+            "columns" | "column" => Ok(Mode::Columns),
+            "rows" | "row" => Ok(Mode::Rows),
+            "global" => Ok(Mode::Global),
+            "loss" => Ok(Mode::Loss),
+            "absolute" => Ok(Mode::Absolute),
+            _ => Err(format!(
+                "unknown scale '{}' (expected columns, rows, global, loss or absolute)",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for scaling a chart's values to colors.
+#[derive(Debug, Clone)]
+pub struct Scaling {
+    pub mode: Mode,
+    /// Fixed color range in the mode's units.  Values outside it are
+    /// clipped to the ends of the color map.
+    pub range: Option<(f32, f32)>,
+    /// When picking the range automatically, ignore this fraction of the
+    /// values at each end.
+    pub clip: f32,
+    /// Space the colors logarithmically.
+    pub log: bool,
+}
+
+/// The mapping from display values to positions on the color map.
+pub struct ColorScale {
+    pub mode: Mode,
+    pub min: f32,
+    pub max: f32,
+    pub log: bool,
+    /// Label for the color bar.
+    pub label: String,
+}
+
+impl ColorScale {
+    /// Position of `value` along the color map, clamped to [0, 1].  NaN
+    /// stays NaN.
+    pub fn position(&self, value: f32) -> f32 {
+        let t = if self.log {
+            (value.ln() - self.min.ln()) / (self.max.ln() - self.min.ln())
+        } else {
+            (value - self.min) / (self.max - self.min)
+        };
+        if t.is_nan() {
+            return if value.is_nan() { t } else { 0.0 };
+        }
+        let t = t.clamp(0.0, 1.0);
+        // Smaller losses are better, so they get the bright end.
+        if self.mode == Mode::Loss {
+            1.0 - t
+        } else {
+            t
+        }
+    }
+
+    /// Labeled ticks for the color bar, as (position, label) pairs.
+    pub fn ticks(&self, target: usize) -> Vec<(f32, String)> {
+        let percent = !matches!(self.mode, Mode::Absolute | Mode::Difference | Mode::Ratio);
+        let format = |v: f32, step: f32| {
+            if percent {
+                format!("{}%", format_tick(v, step))
+            } else {
+                format_tick(v, step)
+            }
+        };
+
+        if self.log {
+            let ticks = log_ticks(self.min, self.max, target);
+            if ticks.len() >= 2 {
+                return ticks
+                    .iter()
+                    .map(|&v| {
+                        let step = 10.0f32.powf(v.log10().floor());
+                        (self.position(v), format(v, step))
+                    })
+                    .collect();
+            }
+        }
+        // Narrow ranges can leave a single tick; ask for more until the
+        // bar is readable.
+        let mut target = target;
+        let (mut ticks, mut step) = nice_ticks(self.min, self.max, target);
+        while ticks.len() < 3 && target < 16 {
+            target *= 2;
+            let (t, s) = nice_ticks(self.min, self.max, target);
+            ticks = t;
+            step = s;
+        }
+        ticks
+            .iter()
+            .filter(|&&v| !self.log || v > 0.0)
+            .map(|&v| (self.position(v), format(v, step)))
+            .collect()
+    }
+
+    /// Where a contour level (a fraction of the column best) falls on the
+    /// color bar, for modes that are relative to the column best.
+    pub fn contour_position(&self, level: f32) -> Option<f32> {
+        match self.mode {
+            Mode::Columns => Some(self.position(level * 100.0)),
+            Mode::Loss => Some(self.position((1.0 - level) * 100.0)),
+            _ => None,
+        }
+    }
+}
+
+/// Converts a chart (row-major, `y * width + x`) into the units of the
+/// scaling mode and works out its color scale.  `label` names the values
+/// for the color bar of absolute charts.
+pub fn apply(chart: &[f32], grid: &Grid, scaling: &Scaling, label: &str) -> (Vec<f32>, ColorScale) {
+    let height = grid.interval_cells as usize;
+    let width = grid.retention_cells as usize;
+    let column_max = |x: usize| max_of((0..height).map(|y| chart[y * width + x]));
+    let row_max = |y: usize| max_of((0..width).map(|x| chart[y * width + x]));
+    let global_max = max_of(chart.iter().cloned());
+
+    let values: Vec<f32> = (0..chart.len())
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            match scaling.mode {
+                Mode::Columns => chart[i] / column_max(x) * 100.0,
+                Mode::Rows => chart[i] / row_max(y) * 100.0,
+                Mode::Global => chart[i] / global_max * 100.0,
+                Mode::Loss => (1.0 - chart[i] / column_max(x)) * 100.0,
+                Mode::Absolute | Mode::Difference | Mode::Ratio => chart[i],
+            }
+        })
+        .collect();
+
+    let (min, max) = match scaling.range {
+        Some(range) => range,
+        None => {
+            let mut finite: Vec<f32> = values.iter().cloned().filter(|v| v.is_finite()).collect();
+            finite.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            if finite.is_empty() {
+                (0.0, 1.0)
+            } else {
+                let lo = finite[((finite.len() - 1) as f32 * scaling.clip).round() as usize];
+                let hi =
+                    finite[((finite.len() - 1) as f32 * (1.0 - scaling.clip)).round() as usize];
+                let smallest_positive = finite.iter().cloned().find(|&v| v > 0.0).unwrap_or(1.0);
+                if scaling.mode == Mode::Difference || scaling.mode == Mode::Ratio {
+                    centered_range(&finite, scaling)
+                } else if scaling.log {
+                    (lo.max(smallest_positive), hi)
+                } else if scaling.clip > 0.0 {
+                    (lo, hi)
+                } else {
+                    // Unclipped linear scales start from zero, so colors
+                    // are proportional to the values.
+                    (lo.min(0.0), hi)
+                }
+            }
+        }
+    };
+    let max = if max > min { max } else { min + 1.0 };
+
+    let label = match scaling.mode {
+        Mode::Columns => "Relative to column best".to_string(),
+        Mode::Rows => "Relative to row best".to_string(),
+        Mode::Global => "Relative to best".to_string(),
+        Mode::Loss => "Loss vs column best".to_string(),
+        Mode::Absolute => label.to_string(),
+        Mode::Difference => "Difference (B - A)".to_string(),
+        Mode::Ratio => "Ratio (B / A)".to_string(),
+    };
+    let scale = ColorScale {
+        mode: scaling.mode,
+        min,
+        max,
+        log: scaling.log,
+        label,
+    };
+    (values, scale)
+}
+
+/// A range centered on zero for differences or one for ratios, wide enough
+/// for all but the clipped fraction of `sorted` values.
+fn centered_range(sorted: &[f32], scaling: &Scaling) -> (f32, f32) {
+    let center = if scaling.mode == Mode::Ratio {
+        1.0
+    } else {
+        0.0
+    };
+    let mut distances: Vec<f32> = sorted
+        .iter()
+        .filter(|&&v| !scaling.log || v > 0.0)
+        .map(|&v| {
+            if scaling.log {
+                v.ln().abs()
+            } else {
+                (v - center).abs()
+            }
+        })
+        .collect();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let reach = match distances.last() {
+        Some(_) => {
+            let i = ((distances.len() - 1) as f32 * (1.0 - scaling.clip)).round() as usize;
+            distances[i].max(1.0e-6)
+        }
+        None => 1.0,
+    };
+    if scaling.log {
+        (center * (-reach).exp(), center * reach.exp())
+    } else {
+        (center - reach, center + reach)
+    }
+}
+
+/// The largest value, ignoring NaN.
+fn max_of<I: Iterator<Item = f32>>(values: I) -> f32 {
+    values.fold(0.0, |acc, v| if acc > v || v.is_nan() { acc } else { v })
+}
+
+/// Ticks at 1, 2 and 5 times powers of ten between `min` and `max`, thinned
+/// to powers of ten alone if that gives too many.
+fn log_ticks(min: f32, max: f32, target: usize) -> Vec<f32> {
+    if min <= 0.0 || max <= min {
+        return Vec::new();
+    }
+    let mut ticks = Vec::new();
+    for &multiples in &[&[1.0, 2.0, 5.0][..], &[1.0][..]] {
+        ticks.clear();
+        let mut decade = 10.0f32.powf(min.log10().floor());
+        while decade <= max {
+            for m in multiples {
+                let v = m * decade;
+                if v >= min * 0.9999 && v <= max * 1.0001 {
+                    ticks.push(v);
+                }
+            }
+            decade *= 10.0;
+        }
+        if ticks.len() <= target.max(2) {
+            break;
+        }
+    }
+    ticks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Grid {
+        Grid {
+            interval_range: (2.0, 3.0),
+            interval_cells: 2,
+            retention_range: (0.5, 1.0),
+            retention_cells: 2,
+        }
+    }
+
+    fn scaling(mode: Mode) -> Scaling {
+        Scaling {
+            mode,
+            range: None,
+            clip: 0.0,
+            log: false,
+        }
+    }
+
+    #[test]
+    fn modes_normalize_by_the_right_best() {
+        let chart = [1.0, 2.0, 4.0, f32::NAN];
+        let values = |mode| apply(&chart, &grid(), &scaling(mode), "x").0;
+        assert_eq!(values(Mode::Columns)[..3], [25.0, 100.0, 100.0]);
+        assert_eq!(values(Mode::Rows)[..3], [50.0, 100.0, 100.0]);
+        assert_eq!(values(Mode::Global)[..3], [25.0, 50.0, 100.0]);
+        assert_eq!(values(Mode::Loss)[..3], [75.0, 0.0, 0.0]);
+        assert!(values(Mode::Absolute)[3].is_nan());
+    }
+
+    #[test]
+    fn ranges_start_at_zero_unless_clipped() {
+        let chart = [1.0, 2.0, 3.0, 4.0];
+        let (_, scale) = apply(&chart, &grid(), &scaling(Mode::Absolute), "x");
+        assert_eq!((scale.min, scale.max), (0.0, 4.0));
+        assert_eq!(scale.position(1.0), 0.25);
+        assert_eq!(scale.position(9.0), 1.0);
+        assert!(scale.position(f32::NAN).is_nan());
+
+        let clipped = Scaling {
+            clip: 0.34,
+            ..scaling(Mode::Absolute)
+        };
+        let (_, scale) = apply(&chart, &grid(), &clipped, "x");
+        assert_eq!((scale.min, scale.max), (2.0, 3.0));
+    }
+
+    #[test]
+    fn differences_and_ratios_are_centered() {
+        let chart = [-1.0, 0.5, 3.0, 0.0];
+        let (_, scale) = apply(&chart, &grid(), &scaling(Mode::Difference), "x");
+        assert_eq!((scale.min, scale.max), (-3.0, 3.0));
+
+        let chart = [0.5, 1.0, 1.5, 1.0];
+        let ratio = Scaling {
+            log: true,
+            ..scaling(Mode::Ratio)
+        };
+        let (_, scale) = apply(&chart, &grid(), &ratio, "x");
+        assert!((scale.min - 0.5).abs() < 1e-6 && (scale.max - 2.0).abs() < 1e-6);
+        assert!((scale.position(1.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn losses_put_small_values_at_the_bright_end() {
+        let (_, scale) = apply(&[1.0, 1.0, 2.0, 2.0], &grid(), &scaling(Mode::Loss), "x");
+        assert_eq!(scale.position(0.0), 1.0);
+        // Losses run 0% to 50%, so losing 10% is 80% of the way up.
+        assert!((scale.contour_position(0.9).unwrap() - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn log_ticks_thin_out() {
+        assert_eq!(log_ticks(1.0, 10.0, 5), vec![1.0, 2.0, 5.0, 10.0]);
+        assert_eq!(log_ticks(1.0, 1000.0, 5), vec![1.0, 10.0, 100.0, 1000.0]);
+        assert!(log_ticks(0.0, 10.0, 5).is_empty());
+    }
+}
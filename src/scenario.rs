@@ -0,0 +1,661 @@
+//! Scenario files: a small subset of TOML describing a complete run.
+//!
+//! Top-level keys are simulation parameters (see `params::PARAM_NAMES`).
+//! A `[grid]` section supplies the grid options shared by the sweeping
+//! commands, `[axis.<param>]` sections define sweep axes, and a section
+//! named after a command (e.g. `[chart]`) supplies that command's own
+//! options.  Command-line flags override anything in the file.
+//!
+//! ```toml
+//! engine = "analytical"
+//! max_lapses = 8
+//! lapse_interval_power = -0.5
+//!
+//! [grid]
+//! interval_range = [2.0, 10.0]
+//! interval_cells = 65
+//!
+//! [axis.max_lapses]
+//! list = [4, 8]       # or: linear = [min, max, cells], log = [...]
+//!
+//! [chart]
+//! output = "yar.png"
+//! normalize_slices = true
+//! ```
+//!
+//! Supported values are strings, numbers, booleans and flat arrays of
+//! those.  Comments start with `#`.  Strings take TOML's escapes, so a
+//! Windows path is written `"C:\\decks\\collection.anki2"`.
+//!
+//! The same scenario can also be written as JSON, in a file ending in
+//! `.json`: sections become objects, and `[axis.<param>]` sections become
+//! objects inside an `"axis"` object.
+//!
+//! ```json
+//! {
+//!     "engine": "analytical",
+//!     "max_lapses": 8,
+//!     "grid": { "interval_range": [2.0, 10.0], "interval_cells": 65 },
+//!     "axis": { "max_lapses": { "list": [4, 8] } },
+//!     "chart": { "output": "yar.png", "normalize_slices": true }
+//! }
+//! ```
+
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    /// A whole, non-negative number, kept exact: seeds go beyond the
+    /// integers an `f64` holds.
+    Int(u64),
+    Num(f64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The value as it would be written on the command line.  Arrays
+    /// become comma-separated lists.
+    pub fn to_arg(&self) -> String {
+        match *self {
+            Value::Str(ref s) => s.clone(),
+            Value::Int(n) => n.to_string(),
+            Value::Num(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(ref a) => a.iter().map(|v| v.to_arg()).collect::<Vec<_>>().join(","),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize, // Where it was given, for error messages.
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String, // Empty for the top-level section.
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub sections: Vec<Section>,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Scenario, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("couldn't read scenario '{}': {}", path, e))?;
+        let scenario = if path.ends_with(".json") {
+            Scenario::parse_json(&text)
+        } else {
+            Scenario::parse(&text)
+        };
+        scenario.map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut sections = vec![Section {
+            name: String::new(),
+            line: 0,
+            entries: Vec::new(),
+        }];
+
+        for (line_i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            let err = |msg: &str| format!("line {}: {}", line_i + 1, msg);
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(err("unterminated section header"));
+                }
+                let name = line[1..(line.len() - 1)].trim();
+                if name.is_empty() {
+                    return Err(err("empty section name"));
+                }
+                sections.push(Section {
+                    name: name.to_string(),
+                    line: line_i + 1,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+
+            let eq = line
+                .find('=')
+                .ok_or_else(|| err("expected 'key = value'"))?;
+            let key = line[..eq].trim();
+            if key.is_empty() {
+                return Err(err("missing key"));
+            }
+            let value = parse_value(line[(eq + 1)..].trim()).map_err(|e| err(&e))?;
+            sections.last_mut().unwrap().entries.push(Entry {
+                key: key.to_string(),
+                value,
+                line: line_i + 1,
+            });
+        }
+
+        Ok(Scenario { sections })
+    }
+
+    /// Parses the JSON form of a scenario.
+    pub fn parse_json(text: &str) -> Result<Scenario, String> {
+        let mut parser = JsonParser { text, pos: 0 };
+        let top = match parser.parse_document()? {
+            Json::Object(members) => members,
+            _ => return Err("line 1: expected a JSON object".to_string()),
+        };
+
+        let mut sections = vec![Section {
+            name: String::new(),
+            line: 0,
+            entries: Vec::new(),
+        }];
+        for (key, line, value) in top {
+            match value {
+                Json::Object(members) if key == "axis" => {
+                    for (param, line, kinds) in members {
+                        let name = format!("axis.{}", param);
+                        sections.push(json_section(name, line, kinds)?);
+                    }
+                }
+                Json::Object(_) => sections.push(json_section(key, line, value)?),
+                value => sections[0].entries.push(Entry {
+                    key,
+                    value: json_value(value).map_err(|e| format!("line {}: {}", line, e))?,
+                    line,
+                }),
+            }
+        }
+        Ok(Scenario { sections })
+    }
+
+    /// All entries of the named section(s), in file order.
+    pub fn section<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.sections
+            .iter()
+            .filter(move |s| s.name == name)
+            .flat_map(|s| s.entries.iter())
+    }
+}
+
+/// Removes a trailing `#` comment, ignoring `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let (unquoted, _) = outside_strings(line);
+    match unquoted.iter().find(|&&(_, c)| c == '#') {
+        Some(&(i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+/// The characters of `s` that aren't inside strings, with their byte
+/// offsets, and whether a string was left open at the end.
+fn outside_strings(s: &str) -> (Vec<(usize, char)>, bool) {
+    let mut unquoted = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in s.char_indices() {
+        if !in_string {
+            in_string = c == '"';
+            unquoted.push((i, c));
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_string = false;
+            unquoted.push((i, c));
+        }
+    }
+    (unquoted, in_string)
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if s.starts_with('[') {
+        if !s.ends_with(']') {
+            return Err("unterminated array".to_string());
+        }
+        let inner = s[1..(s.len() - 1)].trim();
+        if inner.is_empty() {
+            return Ok(Value::Array(Vec::new()));
+        }
+        let items = split_array(inner)?
+            .iter()
+            .map(|item| parse_value(item.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if items.iter().any(|v| matches!(*v, Value::Array(_))) {
+            return Err("nested arrays aren't supported".to_string());
+        }
+        Ok(Value::Array(items))
+    } else if s.starts_with('"') {
+        parse_string(s).map(Value::Str)
+    } else if s == "true" || s == "false" {
+        Ok(Value::Bool(s == "true"))
+    } else {
+        let s = s.replace('_', "");
+        match s.parse::<u64>() {
+            Ok(n) => Ok(Value::Int(n)),
+            Err(_) => s
+                .parse::<f64>()
+                .map(Value::Num)
+                .map_err(|_| format!("invalid value '{}'", s)),
+        }
+    }
+}
+
+/// Splits the inside of an array on commas that aren't in strings.
+fn split_array(s: &str) -> Result<Vec<&str>, String> {
+    let (unquoted, open) = outside_strings(s);
+    if open {
+        return Err("unterminated string".to_string());
+    }
+    let mut items = Vec::new();
+    let mut start = 0;
+    for &(i, c) in &unquoted {
+        if c == ',' {
+            items.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    // Allow a trailing comma.
+    if !s[start..].trim().is_empty() {
+        items.push(&s[start..]);
+    }
+    Ok(items)
+}
+
+/// Writes `s` as a TOML basic string, escaping what `parse_string` undoes.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses a TOML basic string, quotes included, undoing its escapes.
+fn parse_string(s: &str) -> Result<String, String> {
+    let mut string = String::new();
+    let mut chars = s[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(string),
+            '"' => {
+                return Err(format!(
+                    "unexpected text after string: '{}'",
+                    chars.as_str()
+                ))
+            }
+            '\\' => {
+                let c = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some(u) if u == 'u' || u == 'U' => {
+                        let digits = if u == 'u' { 4 } else { 8 };
+                        let hex: String = chars.by_ref().take(digits).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == digits)
+                            .and_then(::std::char::from_u32)
+                            .ok_or_else(|| format!("invalid escape '\\{}{}'", u, hex))?
+                    }
+                    Some(c) => {
+                        return Err(format!(
+                            "invalid escape '\\{}' in string; write a backslash as '\\\\'",
+                            c
+                        ))
+                    }
+                    None => break,
+                };
+                string.push(c);
+            }
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// A parsed JSON value.  Object members keep the line they started on.
+enum Json {
+    Str(String),
+    Num(String),
+    Bool(bool),
+    Array(Vec<Json>),
+    Object(Vec<(String, usize, Json)>),
+}
+
+/// A section from a JSON object of scalar and array members.
+fn json_section(name: String, line: usize, value: Json) -> Result<Section, String> {
+    let members = match value {
+        Json::Object(members) => members,
+        _ => return Err(format!("line {}: '{}' must be an object", line, name)),
+    };
+    let entries = members
+        .into_iter()
+        .map(|(key, line, value)| {
+            Ok(Entry {
+                key,
+                value: json_value(value).map_err(|e| format!("line {}: {}", line, e))?,
+                line,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Section {
+        name,
+        line,
+        entries,
+    })
+}
+
+fn json_value(value: Json) -> Result<Value, String> {
+    match value {
+        Json::Str(s) => Ok(Value::Str(s)),
+        Json::Num(n) => parse_value(&n),
+        Json::Bool(b) => Ok(Value::Bool(b)),
+        Json::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| match item {
+                    Json::Array(_) => Err("nested arrays aren't supported".to_string()),
+                    item => json_value(item),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Array(items))
+        }
+        Json::Object(_) => Err("objects aren't supported here".to_string()),
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn parse_document(&mut self) -> Result<Json, String> {
+        let value = self.parse_value()?;
+        self.skip_space();
+        if self.pos < self.text.len() {
+            return Err(self.err("unexpected text after the top-level value"));
+        }
+        Ok(value)
+    }
+
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn err(&self, msg: &str) -> String {
+        format!("line {}: {}", self.line(), msg)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            return Err(self.err(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Whether the next character is `c`, consuming it if so.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => self.parse_string().map(Json::Str),
+            Some(_) => {
+                let rest = &self.text[self.pos..];
+                let end = rest
+                    .find(|c: char| !(c.is_alphanumeric() || "+-.".contains(c)))
+                    .unwrap_or(rest.len());
+                let word = &rest[..end];
+                let value = match word {
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    "" => return Err(self.err("expected a value")),
+                    _ if word.parse::<f64>().is_ok() => Json::Num(word.to_string()),
+                    _ => return Err(self.err(&format!("invalid value '{}'", word))),
+                };
+                self.pos += end;
+                Ok(value)
+            }
+            None => Err(self.err("unexpected end of file")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        if self.eat('}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_space();
+            let line = self.line();
+            if self.peek() != Some('"') {
+                return Err(self.err("expected a quoted key"));
+            }
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            members.push((key, line, self.parse_value()?));
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(Json::Object(members));
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        if self.eat(']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            if !self.eat(',') {
+                self.expect(']')?;
+                return Ok(Json::Array(items));
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.pos += 1; // The opening quote.
+        let mut s = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 'u')) => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(::std::char::from_u32)
+                            .ok_or_else(|| self.err("invalid \\u escape"))?;
+                        s.push(c);
+                    }
+                    Some((_, c)) if "\"\\/".contains(c) => s.push(c),
+                    _ => return Err(self.err("invalid escape in string")),
+                },
+                c => s.push(c),
+            }
+        }
+        Err(self.err("unterminated string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+engine = "analytical"  # a comment
+max_lapses = 8
+days = 1_000
+
+[grid]
+interval_range = [2.0, 10.0,]
+note = "a # in a string"
+
+[axis.max_lapses]
+list = [4, 8]
+"#;
+
+    const JSON: &str = r#"{
+    "engine": "analytical",
+    "max_lapses": 8,
+    "days": 1000,
+    "grid": { "interval_range": [2.0, 10.0], "note": "a # in a string" },
+    "axis": { "max_lapses": { "list": [4, 8] } }
+}"#;
+
+    fn summary(scenario: &Scenario) -> Vec<(String, String, String)> {
+        scenario
+            .sections
+            .iter()
+            .flat_map(|s| {
+                s.entries
+                    .iter()
+                    .map(move |e| (s.name.clone(), e.key.clone(), e.value.to_arg()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_toml() {
+        let scenario = Scenario::parse(TOML).unwrap();
+        let entries: Vec<_> = scenario.section("").collect();
+        assert_eq!(entries[0].value, Value::Str("analytical".to_string()));
+        assert_eq!(entries[1].value, Value::Int(8));
+        assert_eq!(entries[2].value.to_arg(), "1000");
+        assert_eq!(entries[1].line, 3);
+        let grid: Vec<_> = scenario.section("grid").collect();
+        assert_eq!(grid[0].value.to_arg(), "2,10");
+        assert_eq!(grid[1].value.to_arg(), "a # in a string");
+        assert_eq!(scenario.section("axis.max_lapses").count(), 1);
+    }
+
+    #[test]
+    fn json_matches_toml() {
+        let toml = Scenario::parse(TOML).unwrap();
+        let json = Scenario::parse_json(JSON).unwrap();
+        assert_eq!(summary(&json), summary(&toml));
+        let grid = json.sections.iter().find(|s| s.name == "grid").unwrap();
+        assert_eq!(grid.line, 5);
+    }
+
+    #[test]
+    fn toml_errors_name_the_line() {
+        let err = |text: &str| Scenario::parse(text).unwrap_err();
+        assert!(err("days = 1\n[grid").starts_with("line 2:"));
+        assert!(err("days 1").contains("expected 'key = value'"));
+        assert!(err("a = \"open").contains("unterminated string"));
+        assert!(err("a = [1, [2]]").contains("nested arrays"));
+        assert!(err("a = nope").contains("invalid value"));
+    }
+
+    #[test]
+    fn json_errors_name_the_line() {
+        let err = |text: &str| Scenario::parse_json(text).unwrap_err();
+        assert!(err("{\n\"days\": }").starts_with("line 2:"));
+        assert!(err("[1, 2]").contains("expected a JSON object"));
+        assert!(err("{\"a\": 1} x").contains("unexpected text"));
+        assert!(err("{\"grid\": {\"a\": {\"b\": 1}}}").contains("objects aren't supported"));
+        assert!(err("{\"a\": \"open}").contains("unterminated string"));
+        assert!(err("{\"a\": null}").contains("invalid value"));
+    }
+
+    #[test]
+    fn toml_string_escapes() {
+        let toml = Scenario::parse(
+            r#"path = "C:\\decks\\collection.anki2"  # "quoted" \ comment
+note = "say \"hi\" # not a comment\n\u00e9"
+list = ["a\"b,c", "d"]"#,
+        )
+        .unwrap();
+        let values: Vec<_> = toml.section("").map(|e| e.value.clone()).collect();
+        assert_eq!(
+            values[0],
+            Value::Str("C:\\decks\\collection.anki2".to_string())
+        );
+        assert_eq!(
+            values[1],
+            Value::Str("say \"hi\" # not a comment\n\u{e9}".to_string())
+        );
+        assert_eq!(values[2].to_arg(), "a\"b,c,d");
+
+        let err = |text: &str| Scenario::parse(text).unwrap_err();
+        assert!(err(r#"path = "C:\decks""#).contains("invalid escape '\\d'"));
+        assert!(err(r#"a = "x\""#).contains("unterminated string"));
+        assert!(err(r#"a = "x" y"#).contains("unexpected text"));
+        assert!(err(r#"a = "\u12""#).contains("invalid escape"));
+
+        let text = "C:\\a \"b\"\n\u{1}";
+        assert_eq!(parse_string(&quote(text)).unwrap(), text);
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        let json = Scenario::parse_json(r#"{"a": "x\"y\\z\u0041\n"}"#).unwrap();
+        let a = json.section("").next().unwrap();
+        assert_eq!(a.value, Value::Str("x\"y\\zA\n".to_string()));
+    }
+}
@@ -0,0 +1,166 @@
+//! Summary statistics, mostly over replicate simulation runs.
+
+use std::str::FromStr;
+
+use params::{metric_label, Metrics, METRIC_NAMES};
+
+/// Which statistic of a replicated metric to show.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stat {
+    Mean,
+    /// Standard error of the mean.
+    StdErr,
+    /// Coefficient of variation of the mean: its standard error divided by
+    /// its absolute value.
+    Cv,
+}
+
+impl FromStr for Stat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Stat, String> {
+        match s {
+            "mean" => Ok(Stat::Mean),
+            "std-err" | "std_err" | "se" => Ok(Stat::StdErr),
+            "cv" => Ok(Stat::Cv),
+            _ => Err(format!(
+                "unknown statistic '{}' (expected mean, std-err or cv)",
+                s
+            )),
+        }
+    }
+}
+
+impl Stat {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Stat::Mean => "mean",
+            Stat::StdErr => "standard error",
+            Stat::Cv => "coefficient of variation",
+        }
+    }
+
+    /// A label for this statistic of `metric`, e.g. "Standard error of
+    /// cards learned per hour".
+    pub fn label(&self, metric: &str) -> String {
+        match *self {
+            Stat::Mean => metric_label(metric),
+            Stat::StdErr => format!("Standard error of {}", metric.replace('_', " ")),
+            Stat::Cv => format!("CV of {}", metric.replace('_', " ")),
+        }
+    }
+}
+
+pub fn mean(v: &[f64]) -> f64 {
+    v.iter().sum::<f64>() / v.len() as f64
+}
+
+/// Standard error of the mean.
+pub fn std_err(v: &[f64]) -> f64 {
+    if v.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(v);
+    let var = v.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (v.len() - 1) as f64;
+    (var / v.len() as f64).sqrt()
+}
+
+/// Percentile of already-sorted data, with linear interpolation.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let pos = p * (sorted.len() - 1) as f64;
+    let i = pos.floor() as usize;
+    let j = (i + 1).min(sorted.len() - 1);
+    let alpha = pos - i as f64;
+    sorted[i] * (1.0 - alpha) + sorted[j] * alpha
+}
+
+/// Mean, standard error and spread of a set of samples.
+#[derive(Debug, Copy, Clone)]
+pub struct Summary {
+    pub mean: f64,
+    pub std_err: f64,
+    pub p5: f64,
+    pub p50: f64,
+    pub p95: f64,
+}
+
+pub fn summarize(samples: &[f64]) -> Summary {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+    Summary {
+        mean: mean(samples),
+        std_err: std_err(samples),
+        p5: percentile(&sorted, 0.05),
+        p50: percentile(&sorted, 0.5),
+        p95: percentile(&sorted, 0.95),
+    }
+}
+
+/// Summaries of every metric across replicate runs, in `METRIC_NAMES`
+/// order.
+pub fn summarize_metrics(runs: &[Metrics]) -> Vec<Summary> {
+    METRIC_NAMES
+        .iter()
+        .map(|name| {
+            let samples: Vec<f64> = runs.iter().map(|m| m.get(name).unwrap() as f64).collect();
+            summarize(&samples)
+        })
+        .collect()
+}
+
+/// Each metric's mean and standard error across replicate runs.
+pub fn mean_and_std_err(runs: &[Metrics]) -> (Metrics, Metrics) {
+    let mut means = Metrics::default();
+    let mut errs = Metrics::default();
+    for (name, summary) in METRIC_NAMES.iter().zip(summarize_metrics(runs)) {
+        *means.get_mut(name).unwrap() = summary.mean as f32;
+        *errs.get_mut(name).unwrap() = summary.std_err as f32;
+    }
+    (means, errs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn means_and_standard_errors() {
+        let v = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(mean(&v), 2.5);
+        // Sample variance 5/3, over 4 samples.
+        assert!((std_err(&v) - (5.0f64 / 12.0).sqrt()).abs() < 1e-12);
+        assert_eq!(std_err(&[7.0]), 0.0);
+    }
+
+    #[test]
+    fn percentiles_interpolate() {
+        let sorted = [0.0, 10.0, 20.0];
+        assert_eq!(percentile(&sorted, 0.0), 0.0);
+        assert_eq!(percentile(&sorted, 0.25), 5.0);
+        assert_eq!(percentile(&sorted, 1.0), 20.0);
+        assert_eq!(percentile(&[3.0], 0.5), 3.0);
+    }
+
+    #[test]
+    fn summaries_sort_their_samples() {
+        let s = summarize(&[20.0, 0.0, 10.0]);
+        assert_eq!((s.mean, s.p50), (10.0, 10.0));
+        assert_eq!(s.p5, 1.0);
+        assert_eq!(s.p95, 19.0);
+    }
+
+    #[test]
+    fn summarizes_each_metric() {
+        let runs: Vec<Metrics> = [1.0, 3.0]
+            .iter()
+            .map(|&v| {
+                let mut m = Metrics::default();
+                *m.get_mut("known_cards").unwrap() = v;
+                m
+            })
+            .collect();
+        let (means, errs) = mean_and_std_err(&runs);
+        assert_eq!(means.get("known_cards"), Some(2.0));
+        assert_eq!(errs.get("known_cards"), Some(1.0));
+    }
+}
@@ -0,0 +1,352 @@
+//! A minimal SVG writer, plus line plots built on it.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use chart::{format_tick, nice_ticks};
+use image::Rgb;
+
+pub struct Svg {
+    width: f32,
+    height: f32,
+    body: String,
+}
+
+impl Svg {
+    pub fn new(width: f32, height: f32) -> Svg {
+        Svg {
+            width,
+            height,
+            body: String::new(),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    /// Adds raw SVG markup.
+    pub fn raw(&mut self, markup: &str) {
+        self.body.push_str(markup);
+        self.body.push('\n');
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, fill: Rgb) {
+        self.raw(&format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"{}\"/>",
+            x,
+            y,
+            w,
+            h,
+            color(fill)
+        ));
+    }
+
+    pub fn outline_rect(&mut self, x: f32, y: f32, w: f32, h: f32, stroke: Rgb) {
+        self.raw(&format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"none\" stroke=\"{}\"/>",
+            x,
+            y,
+            w,
+            h,
+            color(stroke)
+        ));
+    }
+
+    pub fn line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, stroke: Rgb) {
+        self.raw(&format!(
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+            from.0,
+            from.1,
+            to.0,
+            to.1,
+            color(stroke),
+            width
+        ));
+    }
+
+    pub fn polyline(&mut self, points: &[(f32, f32)], width: f32, stroke: Rgb) {
+        let coords: Vec<String> = points
+            .iter()
+            .map(|p| format!("{:.2},{:.2}", p.0, p.1))
+            .collect();
+        self.raw(&format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\"/>",
+            coords.join(" "),
+            color(stroke),
+            width
+        ));
+    }
+
+    pub fn circle(&mut self, center: (f32, f32), r: f32, fill: Option<Rgb>, stroke: Rgb) {
+        self.raw(&format!(
+            "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{}\" fill=\"{}\" stroke=\"{}\"/>",
+            center.0,
+            center.1,
+            r,
+            fill.map(color).unwrap_or_else(|| "none".to_string()),
+            color(stroke)
+        ));
+    }
+
+    /// Draws text.  `anchor` is "start", "middle" or "end", and `(x, y)` is
+    /// on the text's baseline.
+    pub fn text(&mut self, x: f32, y: f32, text: &str, size: f32, anchor: &str) {
+        self.raw(&format!(
+            "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"{}\" text-anchor=\"{}\">{}</text>",
+            x,
+            y,
+            size,
+            anchor,
+            escape(text)
+        ));
+    }
+
+    /// Draws text rotated to read bottom to top, centered on `(x, y)`.
+    pub fn text_vertical(&mut self, x: f32, y: f32, text: &str, size: f32) {
+        self.raw(&format!(
+            "<text x=\"0\" y=\"0\" font-size=\"{}\" text-anchor=\"middle\" transform=\"translate({:.2},{:.2}) rotate(-90)\">{}</text>",
+            size,
+            x,
+            y,
+            escape(text)
+        ));
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        writeln!(
+            f,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\">",
+            w = self.width,
+            h = self.height
+        )?;
+        writeln!(f, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>")?;
+        f.write_all(self.body.as_bytes())?;
+        writeln!(f, "</svg>")
+    }
+}
+
+pub fn color(c: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A labeled series of points for a line plot.
+pub struct Series {
+    pub label: String,
+    pub points: Vec<(f32, f32)>,
+}
+
+// Distinct line colors for overlaid series (ColorBrewer "Set1").
+const SERIES_COLORS: [Rgb; 8] = [
+    [228, 26, 28],
+    [55, 126, 184],
+    [77, 175, 74],
+    [152, 78, 163],
+    [255, 127, 0],
+    [166, 86, 40],
+    [247, 129, 191],
+    [153, 153, 153],
+];
+
+/// Writes an SVG line plot of one or more series, with a legend when
+/// there's more than one.
+pub fn write_line_plot(
+    path: &str,
+    title: &str,
+    x_label: &str,
+    y_label: &str,
+    series: &[Series],
+) -> io::Result<()> {
+    let (plot_w, plot_h) = (640.0, 400.0);
+    let (left, top) = (80.0, 50.0);
+    let legend_w = if series.len() > 1 { 160.0 } else { 0.0 };
+    let mut svg = Svg::new(left + plot_w + 30.0 + legend_w, top + plot_h + 70.0);
+
+    // Data ranges, with the y axis padded a little.
+    let all = series
+        .iter()
+        .flat_map(|s| s.points.iter())
+        .filter(|p| p.1.is_finite());
+    let (mut x_min, mut x_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    let (mut y_min, mut y_max) = (f32::INFINITY, f32::NEG_INFINITY);
+    for p in all {
+        x_min = x_min.min(p.0);
+        x_max = x_max.max(p.0);
+        y_min = y_min.min(p.1);
+        y_max = y_max.max(p.1);
+    }
+    if !x_min.is_finite() {
+        x_min = 0.0;
+        x_max = 1.0;
+        y_min = 0.0;
+        y_max = 1.0;
+    }
+    let pad = ((y_max - y_min) * 0.05).max(1.0e-6);
+    y_min -= pad;
+    y_max += pad;
+    if x_max <= x_min {
+        x_max = x_min + 1.0;
+    }
+
+    let to_x = |x: f32| left + (x - x_min) / (x_max - x_min) * plot_w;
+    let to_y = |y: f32| top + plot_h - (y - y_min) / (y_max - y_min) * plot_h;
+
+    // Grid lines and ticks.
+    let (ticks, step) = nice_ticks(x_min, x_max, 8);
+    for t in ticks {
+        let x = to_x(t);
+        svg.line((x, top), (x, top + plot_h), 1.0, [230, 230, 230]);
+        svg.line((x, top + plot_h), (x, top + plot_h + 5.0), 1.0, [0, 0, 0]);
+        svg.text(
+            x,
+            top + plot_h + 20.0,
+            &format_tick(t, step),
+            12.0,
+            "middle",
+        );
+    }
+    let (ticks, step) = nice_ticks(y_min, y_max, 6);
+    for t in ticks {
+        let y = to_y(t);
+        svg.line((left, y), (left + plot_w, y), 1.0, [230, 230, 230]);
+        svg.line((left - 5.0, y), (left, y), 1.0, [0, 0, 0]);
+        svg.text(left - 8.0, y + 4.0, &format_tick(t, step), 12.0, "end");
+    }
+    svg.outline_rect(left, top, plot_w, plot_h, [0, 0, 0]);
+
+    // Labels.
+    svg.text(left + plot_w / 2.0, top - 20.0, title, 18.0, "middle");
+    svg.text(
+        left + plot_w / 2.0,
+        top + plot_h + 45.0,
+        x_label,
+        14.0,
+        "middle",
+    );
+    svg.text_vertical(20.0, top + plot_h / 2.0, y_label, 14.0);
+
+    // The series themselves.
+    for (i, s) in series.iter().enumerate() {
+        let c = SERIES_COLORS[i % SERIES_COLORS.len()];
+        let points: Vec<(f32, f32)> = s
+            .points
+            .iter()
+            .filter(|p| p.1.is_finite())
+            .map(|p| (to_x(p.0), to_y(p.1)))
+            .collect();
+        svg.polyline(&points, 2.0, c);
+    }
+
+    // Legend.
+    if series.len() > 1 {
+        let lx = left + plot_w + 20.0;
+        for (i, s) in series.iter().enumerate() {
+            let c = SERIES_COLORS[i % SERIES_COLORS.len()];
+            let y = top + 10.0 + i as f32 * 20.0;
+            svg.line((lx, y), (lx + 24.0, y), 2.0, c);
+            svg.text(lx + 30.0, y + 4.0, &s.label, 12.0, "start");
+        }
+    }
+
+    svg.write(path)
+}
+
+/// A titled set of labeled bars for a bar chart.
+pub struct Bars {
+    pub title: String,
+    pub x_label: String,
+    pub bars: Vec<(String, f32)>,
+}
+
+/// Writes an SVG of bar charts, one above another.
+pub fn write_bar_charts(path: &str, title: &str, charts: &[Bars]) -> io::Result<()> {
+    let (plot_w, plot_h) = (640.0, 200.0);
+    let (left, top) = (80.0, 60.0);
+    let panel_h = plot_h + 90.0;
+    let mut svg = Svg::new(left + plot_w + 30.0, top + panel_h * charts.len() as f32);
+    svg.text(left + plot_w / 2.0, 30.0, title, 18.0, "middle");
+
+    for (i, chart) in charts.iter().enumerate() {
+        let top = top + panel_h * i as f32 + 20.0;
+        let max = chart
+            .bars
+            .iter()
+            .map(|b| b.1)
+            .filter(|v| v.is_finite())
+            .fold(0.0f32, f32::max);
+        let max = if max > 0.0 { max * 1.05 } else { 1.0 };
+        let to_y = |v: f32| top + plot_h - v / max * plot_h;
+
+        let (ticks, step) = nice_ticks(0.0, max, 5);
+        for t in ticks {
+            let y = to_y(t);
+            svg.line((left, y), (left + plot_w, y), 1.0, [230, 230, 230]);
+            svg.line((left - 5.0, y), (left, y), 1.0, [0, 0, 0]);
+            svg.text(left - 8.0, y + 4.0, &format_tick(t, step), 12.0, "end");
+        }
+
+        // Label every bar, or every few when they'd overlap.
+        let slot = plot_w / chart.bars.len().max(1) as f32;
+        let label_w = chart.bars.iter().map(|b| b.0.len()).max().unwrap_or(1) as f32 * 7.0 + 6.0;
+        let label_every = (label_w / slot).ceil().max(1.0) as usize;
+        for (j, &(ref label, value)) in chart.bars.iter().enumerate() {
+            let x = left + slot * j as f32;
+            if value.is_finite() && value > 0.0 {
+                let y = to_y(value);
+                svg.rect(
+                    x + slot * 0.1,
+                    y,
+                    slot * 0.8,
+                    top + plot_h - y,
+                    SERIES_COLORS[1],
+                );
+            }
+            if j % label_every == 0 {
+                svg.text(x + slot / 2.0, top + plot_h + 18.0, label, 12.0, "middle");
+            }
+        }
+        svg.outline_rect(left, top, plot_w, plot_h, [0, 0, 0]);
+
+        svg.text(left + plot_w / 2.0, top - 8.0, &chart.title, 14.0, "middle");
+        svg.text(
+            left + plot_w / 2.0,
+            top + plot_h + 42.0,
+            &chart.x_label,
+            12.0,
+            "middle",
+        );
+        svg.text_vertical(20.0, top + plot_h / 2.0, "Cards", 12.0);
+    }
+
+    svg.write(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_escaped_markup() {
+        assert_eq!(color([255, 0, 16]), "#ff0010");
+        let mut svg = Svg::new(100.0, 50.0);
+        svg.text(1.0, 2.0, "a < b & c", 12.0, "middle");
+        assert!(svg.body.contains(">a &lt; b &amp; c</text>"));
+
+        let path = ::std::env::temp_dir().join("anki_sim_test.svg");
+        let path = path.to_str().unwrap();
+        svg.write(path).unwrap();
+        let text = ::std::fs::read_to_string(path);
+        ::std::fs::remove_file(path).unwrap();
+        let text = text.unwrap();
+        assert!(text.starts_with("<svg ") && text.trim_end().ends_with("</svg>"));
+        assert!(text.contains("viewBox=\"0 0 100 50\""));
+        assert!(svg.write("/nonexistent/dir/chart.svg").is_err());
+    }
+}
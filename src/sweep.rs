@@ -0,0 +1,783 @@
+//! N-dimensional parameter sweeps.
+//!
+//! Any numeric simulation parameter can be an axis.  Every combination of
+//! axis values is simulated, and the results are stored in row-major order
+//! with the first axis varying slowest.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use rand::random;
+
+use anki_sim;
+use cli;
+use params::{Engine, Metrics, Params, METRIC_NAMES};
+use scenario::{self, Entry, Scenario, Value};
+use stats::{self, Stat};
+
+#[derive(Debug, Clone)]
+pub struct Axis {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+impl Axis {
+    /// Evenly spaced values from `min` to `max`, inclusive.
+    pub fn linear(name: &str, min: f32, max: f32, cells: u32) -> Axis {
+        let step = if cells > 1 {
+            (max - min) / (cells - 1) as f32
+        } else {
+            0.0
+        };
+        Axis {
+            name: name.to_string(),
+            values: (0..cells).map(|i| min + (step * i as f32)).collect(),
+        }
+    }
+
+    /// Geometrically spaced values from `min` to `max`, inclusive.
+    pub fn log(name: &str, min: f32, max: f32, cells: u32) -> Axis {
+        let mut axis = Axis::linear(name, min.ln(), max.ln(), cells);
+        for v in &mut axis.values {
+            *v = v.exp();
+        }
+        axis
+    }
+
+    /// Parses an axis spec of the form `name=kind:values`, where kind is
+    /// one of:
+    ///
+    /// - `linear:min,max,cells`
+    /// - `log:min,max,cells`
+    /// - `list:v1,v2,...`
+    pub fn parse(spec: &str) -> Result<Axis, String> {
+        let err = || {
+            format!(
+                "invalid axis '{}' (expected e.g. 'interval_factor=linear:2,10,65')",
+                spec
+            )
+        };
+        let eq = spec.find('=').ok_or_else(err)?;
+        let name = spec[..eq].trim().replace('-', "_");
+        let rest = &spec[(eq + 1)..];
+        let colon = rest.find(':').ok_or_else(err)?;
+        let kind = rest[..colon].trim();
+        let fields: Vec<&str> = rest[(colon + 1)..].split(',').map(|n| n.trim()).collect();
+        let numbers = fields
+            .iter()
+            .map(|n| n.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| err())?;
+        let cells = || -> Result<u32, String> {
+            match fields[2].parse::<u32>() {
+                Ok(cells) if cells >= 2 => Ok(cells),
+                _ => Err(format!(
+                    "axis '{}' needs a whole number of at least 2 cells, not '{}'",
+                    name, fields[2]
+                )),
+            }
+        };
+
+        let axis = match (kind, numbers.len()) {
+            ("linear", 3) => Axis::linear(&name, numbers[0], numbers[1], cells()?),
+            ("log", 3) => {
+                if numbers[0] <= 0.0 || numbers[1] <= 0.0 {
+                    return Err(format!("log axis '{}' must be positive", name));
+                }
+                Axis::log(&name, numbers[0], numbers[1], cells()?)
+            }
+            ("list", n) if n > 0 => Axis {
+                name: name.clone(),
+                values: numbers,
+            },
+            _ => return Err(err()),
+        };
+
+        if axis.values.is_empty() {
+            return Err(format!("axis '{}' has no values", name));
+        }
+        Params::default().set_value(&axis.name, axis.values[0] as f64)?;
+
+        Ok(axis)
+    }
+}
+
+/// Total number of cells in a sweep over `axes`.
+pub fn cell_count(axes: &[Axis]) -> usize {
+    axes.iter().map(|a| a.values.len()).product()
+}
+
+/// The per-axis value indices of cell `i`.
+pub fn cell_coords(axes: &[Axis], mut i: usize) -> Vec<usize> {
+    let mut coords = vec![0; axes.len()];
+    for (a, axis) in axes.iter().enumerate().rev() {
+        coords[a] = i % axis.values.len();
+        i /= axis.values.len();
+    }
+    coords
+}
+
+/// `base` with the axis values of cell `i` applied.
+pub fn cell_params(base: &Params, axes: &[Axis], i: usize) -> Params {
+    let mut params = base.clone();
+    for (axis, &c) in axes.iter().zip(cell_coords(axes, i).iter()) {
+        // Axis names were validated when the axis was created.
+        params.set_value(&axis.name, axis.values[c] as f64).unwrap();
+    }
+    params
+}
+
+/// Simulates every cell of the sweep, printing progress to stderr.
+pub fn run(base: &Params, axes: &[Axis]) -> Vec<Metrics> {
+    SweepData::run(base, axes, Sampling::fixed(1)).results
+}
+
+/// How many times each cell of a Monte Carlo sweep is simulated, and with
+/// which random numbers.  The analytical engine is deterministic, so it
+/// always runs once.
+#[derive(Debug, Copy, Clone)]
+pub struct Sampling {
+    /// Replicates per cell, or the fewest an adaptive cell uses.
+    pub replicates: u32,
+    /// Keep adding replicates to a cell until the standard error of its
+    /// cards_learned_per_hour is below this.
+    pub tolerance: Option<f32>,
+    /// The most replicates an adaptive cell may use.
+    pub max_replicates: u32,
+    /// Give every cell the same random numbers, so differences between
+    /// cells come from their settings rather than from sampling.
+    pub common: bool,
+    /// Make each replicate an antithetic pair of runs, the second using
+    /// the mirror image of the first's random numbers.
+    pub antithetic: bool,
+}
+
+impl Sampling {
+    pub fn fixed(replicates: u32) -> Sampling {
+        Sampling {
+            replicates,
+            tolerance: None,
+            max_replicates: replicates,
+            common: false,
+            antithetic: false,
+        }
+    }
+
+    /// Reads `--replicates`, `--tolerance`, `--max-replicates`,
+    /// `--common-random-numbers` and `--antithetic`.
+    pub fn from_args(args: &mut cli::Args, default_replicates: u32) -> Result<Sampling, String> {
+        let tolerance = match args.take("tolerance") {
+            Some(t) => match t.parse::<f32>() {
+                Ok(t) if t > 0.0 => Some(t),
+                _ => return Err(format!("invalid value '{}' for --tolerance", t)),
+            },
+            None => None,
+        };
+        // The standard error of only a few replicates is itself too noisy
+        // to stop on, so adaptive cells start from at least four by
+        // default, and never fewer than two.
+        let default_replicates = if tolerance.is_some() {
+            default_replicates.max(4)
+        } else {
+            default_replicates
+        };
+        let mut sampling = Sampling {
+            common: args.get("common-random-numbers", false)?,
+            antithetic: args.get("antithetic", false)?,
+            ..Sampling::fixed(args.get("replicates", default_replicates)?.max(1))
+        };
+        if tolerance.is_none() {
+            return Ok(sampling);
+        }
+        sampling.replicates = sampling.replicates.max(2);
+        sampling.tolerance = tolerance;
+        sampling.max_replicates = args.get("max-replicates", 64u32)?;
+        if sampling.max_replicates < sampling.replicates {
+            return Err(format!(
+                "--max-replicates must be at least {} with --tolerance",
+                sampling.replicates
+            ));
+        }
+        Ok(sampling)
+    }
+
+    /// Simulates `params` as many times as this calls for, returning every
+    /// run.
+    pub fn simulate(&self, params: &Params) -> Vec<Metrics> {
+        if params.engine == Engine::Analytical {
+            return vec![params.simulate()];
+        }
+        let mut runs: Vec<Metrics> = (0..self.replicates)
+            .map(|r| self.replicate(params, r))
+            .collect();
+        if let Some(tolerance) = self.tolerance {
+            let mut samples: Vec<f64> = runs
+                .iter()
+                .map(|m| m.cards_learned_per_hour as f64)
+                .collect();
+            while (runs.len() as u32) < self.max_replicates
+                && stats::std_err(&samples) >= tolerance as f64
+            {
+                let m = self.replicate(params, runs.len() as u32);
+                samples.push(m.cards_learned_per_hour as f64);
+                runs.push(m);
+            }
+        }
+        runs
+    }
+
+    /// Replicate `r` of a cell.  Replicates get seeds derived from the
+    /// cell's seed, if it has one, so that cells sharing a seed share the
+    /// random numbers of every replicate.
+    fn replicate(&self, params: &Params, r: u32) -> Metrics {
+        let mut params = params.clone();
+        params.seed = Some(match params.seed {
+            Some(seed) => anki_sim::mix_seed(seed, r as u64),
+            None => random(),
+        });
+        if !self.antithetic {
+            return params.simulate();
+        }
+        let first = params.simulate();
+        params.antithetic = true;
+        let second = params.simulate();
+        stats::mean_and_std_err(&[first, second]).0
+    }
+}
+
+/// Writes a tidy tab-separated table: one row per cell, with a column for
+/// each axis followed by a column for each metric, then for each metric's
+/// standard error if there is one, and then the replicates each cell used
+/// if that varied.
+pub fn write_table<W: Write>(
+    out: &mut W,
+    axes: &[Axis],
+    results: &[Metrics],
+    std_errs: Option<&[Metrics]>,
+    samples: Option<&[u32]>,
+) -> io::Result<()> {
+    let mut header: Vec<String> = axes
+        .iter()
+        .map(|a| a.name.clone())
+        .chain(METRIC_NAMES.iter().map(|n| n.to_string()))
+        .collect();
+    if std_errs.is_some() {
+        header.extend(METRIC_NAMES.iter().map(|n| format!("{}_std_err", n)));
+    }
+    if samples.is_some() {
+        header.push("replicates".to_string());
+    }
+    writeln!(out, "{}", header.join("\t"))?;
+
+    for (i, metrics) in results.iter().enumerate() {
+        let mut row: Vec<String> = axes
+            .iter()
+            .zip(cell_coords(axes, i).iter())
+            .map(|(axis, &c)| axis.values[c].to_string())
+            .collect();
+        for name in METRIC_NAMES {
+            row.push(metrics.get(name).unwrap().to_string());
+        }
+        if let Some(errs) = std_errs {
+            for name in METRIC_NAMES {
+                row.push(errs[i].get(name).unwrap().to_string());
+            }
+        }
+        if let Some(samples) = samples {
+            row.push(samples[i].to_string());
+        }
+        writeln!(out, "{}", row.join("\t"))?;
+    }
+
+    Ok(())
+}
+
+/// A finished sweep: the base parameters, the axes and every metric of
+/// every cell.  Saved as a data file so charts can be re-rendered without
+/// re-simulating.
+///
+/// The file uses scenario syntax.  Top-level keys are the base parameters
+/// and `[axis.<param>]` sections list each axis' values, so the file also
+/// works as a `--scenario` that re-runs the same sweep.  Each
+/// `[metric.<name>]` section holds one value per cell in sweep order, and
+/// `[std_err.<name>]` sections their standard errors if the sweep was
+/// replicated.  Adaptive sweeps add a `[samples]` section with the number
+/// of replicates each cell used.
+pub struct SweepData {
+    pub params: Params,
+    pub axes: Vec<Axis>,
+    pub results: Vec<Metrics>, // The mean of each cell's replicates.
+    pub std_errs: Option<Vec<Metrics>>,
+    pub samples: Option<Vec<u32>>,
+    /// The path of the starting deck the sweep ran from.  Loading a data
+    /// file only records it: drawing doesn't need the cards, which may
+    /// have moved or changed since.
+    pub starting_deck: Option<String>,
+}
+
+impl SweepData {
+    /// Runs the sweep, printing progress to stderr.
+    pub fn run(base: &Params, axes: &[Axis], sampling: Sampling) -> SweepData {
+        let count = cell_count(axes);
+        let mut results = Vec::with_capacity(count);
+        let mut std_errs = Vec::with_capacity(count);
+        let mut samples = Vec::with_capacity(count);
+
+        // Cells share one seed for common random numbers.  Otherwise a
+        // fixed seed still gives each cell its own, so the sweep can be
+        // repeated exactly.
+        let mut base = base.clone();
+        if sampling.common && base.seed.is_none() {
+            // Small enough to survive a round trip through a data file.
+            base.seed = Some(random::<u64>() >> 11);
+        }
+
+        eprint!("\n0.0%");
+        for i in 0..count {
+            let mut params = cell_params(&base, axes, i);
+            if !sampling.common {
+                params.seed = base.seed.map(|seed| anki_sim::mix_seed(seed, i as u64));
+            }
+            let runs = sampling.simulate(&params);
+            let (mean, std_err) = stats::mean_and_std_err(&runs);
+            results.push(mean);
+            std_errs.push(std_err);
+            samples.push(runs.len() as u32);
+            eprint!("\r{:.1}%", (i + 1) as f32 / count as f32 * 100.0);
+        }
+        eprintln!("\nDone.");
+
+        let adaptive = sampling.tolerance.is_some() && base.engine == Engine::MonteCarlo;
+        if adaptive {
+            let total: u32 = samples.iter().sum();
+            let capped = samples
+                .iter()
+                .filter(|&&n| n >= sampling.max_replicates)
+                .count();
+            eprintln!(
+                "{} replicates over {} cells ({} to {} per cell); {} cells hit --max-replicates.",
+                total,
+                count,
+                samples.iter().min().unwrap_or(&0),
+                samples.iter().max().unwrap_or(&0),
+                capped
+            );
+        }
+        let replicated = samples.iter().any(|&n| n > 1);
+        SweepData {
+            starting_deck: base.starting_deck.as_ref().map(|d| d.path.clone()),
+            params: base,
+            axes: axes.to_vec(),
+            results,
+            std_errs: if replicated { Some(std_errs) } else { None },
+            samples: if adaptive { Some(samples) } else { None },
+        }
+    }
+
+    /// Per-cell values of the given statistic.
+    pub fn values(&self, stat: Stat) -> Result<Vec<Metrics>, String> {
+        let std_errs = match (stat, &self.std_errs) {
+            (Stat::Mean, _) => return Ok(self.results.clone()),
+            (_, Some(errs)) => errs,
+            (_, None) => {
+                return Err(format!(
+                    "charting the {} needs a sweep with --replicates of 2 or more",
+                    stat.name()
+                ))
+            }
+        };
+        Ok(match stat {
+            Stat::Cv => self
+                .results
+                .iter()
+                .zip(std_errs.iter())
+                .map(|(mean, err)| {
+                    let mut cv = Metrics::default();
+                    for name in METRIC_NAMES {
+                        *cv.get_mut(name).unwrap() =
+                            err.get(name).unwrap() / mean.get(name).unwrap().abs();
+                    }
+                    cv
+                })
+                .collect(),
+            _ => std_errs.clone(),
+        })
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# anki_sim sweep data")?;
+        self.params.write(out)?;
+        if let (None, Some(path)) = (&self.params.starting_deck, &self.starting_deck) {
+            writeln!(out, "starting_deck = {}", scenario::quote(path))?;
+        }
+        for axis in &self.axes {
+            writeln!(out, "\n[axis.{}]", axis.name)?;
+            writeln!(out, "list = [{}]", join(axis.values.iter().cloned()))?;
+        }
+        for name in METRIC_NAMES {
+            writeln!(out, "\n[metric.{}]", name)?;
+            writeln!(
+                out,
+                "values = [{}]",
+                join(self.results.iter().map(|m| m.get(name).unwrap()))
+            )?;
+        }
+        if let Some(ref errs) = self.std_errs {
+            for name in METRIC_NAMES {
+                writeln!(out, "\n[std_err.{}]", name)?;
+                writeln!(
+                    out,
+                    "values = [{}]",
+                    join(errs.iter().map(|m| m.get(name).unwrap()))
+                )?;
+            }
+        }
+        if let Some(ref samples) = self.samples {
+            writeln!(out, "\n[samples]")?;
+            writeln!(
+                out,
+                "values = [{}]",
+                join(samples.iter().map(|&n| n as f32))
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        File::create(path)
+            .and_then(|mut f| self.write(&mut f))
+            .map_err(|e| format!("couldn't write '{}': {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<SweepData, String> {
+        let file = Scenario::load(path)?;
+        let err = |msg: String| format!("{}: {}", path, msg);
+
+        let mut params = Params::default();
+        let mut starting_deck = None;
+        for e in file.section("") {
+            if e.key == "starting_deck" {
+                starting_deck = Some(e.value.to_arg());
+                continue;
+            }
+            params.set(&e.key, &e.value.to_arg()).map_err(&err)?;
+        }
+
+        let mut axes = Vec::new();
+        for section in &file.sections {
+            if section.name.starts_with("axis.") {
+                let name = &section.name[5..];
+                let values = numbers(section.entries.iter().find(|e| e.key == "list"))
+                    .filter(|v| !v.is_empty())
+                    .ok_or_else(|| err(format!("axis '{}' has no values", name)))?;
+                Params::default()
+                    .set_value(name, values[0] as f64)
+                    .map_err(&err)?;
+                axes.push(Axis {
+                    name: name.to_string(),
+                    values,
+                });
+            }
+        }
+        let count = cell_count(&axes);
+        if axes.is_empty() {
+            return Err(err("no sweep axes".to_string()));
+        }
+
+        let read_metrics = |kind: &str| -> Result<Option<Vec<Metrics>>, String> {
+            if !file.sections.iter().any(|s| s.name.starts_with(kind)) {
+                return Ok(None);
+            }
+            let mut results = vec![Metrics::default(); count];
+            for name in METRIC_NAMES {
+                let section = format!("{}.{}", kind, name);
+                // Files saved before a metric existed lack its section.
+                if !file.sections.iter().any(|s| s.name == section) {
+                    for m in &mut results {
+                        *m.get_mut(name).unwrap() = f32::NAN;
+                    }
+                    continue;
+                }
+                let values = numbers(file.section(&section).find(|e| e.key == "values"))
+                    .ok_or_else(|| err(format!("missing {} '{}'", kind, name)))?;
+                if values.len() != count {
+                    return Err(err(format!(
+                        "{} '{}' has {} values for {} cells",
+                        kind,
+                        name,
+                        values.len(),
+                        count
+                    )));
+                }
+                for (m, v) in results.iter_mut().zip(values) {
+                    *m.get_mut(name).unwrap() = v;
+                }
+            }
+            Ok(Some(results))
+        };
+        let results = read_metrics("metric")?.ok_or_else(|| err("no metrics".to_string()))?;
+        let std_errs = read_metrics("std_err")?;
+        let samples = match numbers(file.section("samples").find(|e| e.key == "values")) {
+            Some(ref values) if values.len() != count => {
+                return Err(err(format!(
+                    "samples has {} values for {} cells",
+                    values.len(),
+                    count
+                )))
+            }
+            values => values.map(|v| v.iter().map(|&n| n as u32).collect()),
+        };
+
+        Ok(SweepData {
+            params,
+            axes,
+            results,
+            std_errs,
+            samples,
+            starting_deck,
+        })
+    }
+
+    /// The same data with its axes in the given order.  `order[i]` is the
+    /// index of the current axis that becomes axis `i`.
+    pub fn reorder(&self, order: &[usize]) -> SweepData {
+        let axes: Vec<Axis> = order.iter().map(|&a| self.axes[a].clone()).collect();
+        // The old cell index of each new cell.
+        let old_cells: Vec<usize> = (0..cell_count(&axes))
+            .map(|i| {
+                let coords = cell_coords(&axes, i);
+                let mut old = 0;
+                for (a, axis) in self.axes.iter().enumerate() {
+                    let new_a = order.iter().position(|&o| o == a).unwrap();
+                    old = (old * axis.values.len()) + coords[new_a];
+                }
+                old
+            })
+            .collect();
+        let pick = |v: &Vec<Metrics>| old_cells.iter().map(|&i| v[i]).collect();
+        SweepData {
+            params: self.params.clone(),
+            axes,
+            results: pick(&self.results),
+            std_errs: self.std_errs.as_ref().map(pick),
+            samples: self
+                .samples
+                .as_ref()
+                .map(|v| old_cells.iter().map(|&i| v[i]).collect()),
+            starting_deck: self.starting_deck.clone(),
+        }
+    }
+}
+
+fn join<I: Iterator<Item = f32>>(values: I) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// The numbers in an array entry, if it is one.
+fn numbers(entry: Option<&Entry>) -> Option<Vec<f32>> {
+    match entry.map(|e| &e.value) {
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|v| match *v {
+                Value::Int(n) => Some(n as f32),
+                Value::Num(n) => Some(n as f32),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_axes() {
+        let axis = Axis::parse("interval-factor=linear:2,10,5").unwrap();
+        assert_eq!(axis.name, "interval_factor");
+        assert_eq!(axis.values, vec![2.0, 4.0, 6.0, 8.0, 10.0]);
+        let axis = Axis::parse("days=log:10,1000,3").unwrap();
+        assert!((axis.values[1] - 100.0).abs() < 1e-3);
+        let axis = Axis::parse("max_lapses=list:4,8").unwrap();
+        assert_eq!(axis.values, vec![4.0, 8.0]);
+    }
+
+    #[test]
+    fn rejects_bad_axes() {
+        assert!(Axis::parse("interval_factor").is_err());
+        assert!(Axis::parse("interval_factor=linear:2,10").is_err());
+        assert!(Axis::parse("interval_factor=cubic:2,10,3").is_err());
+        assert!(Axis::parse("interval_factor=log:0,10,3").is_err());
+        assert!(Axis::parse("interval_factor=linear:2,10,0").is_err());
+        assert!(Axis::parse("interval_factor=linear:2,10,1").is_err());
+        assert!(Axis::parse("interval_factor=log:2,10,2.9")
+            .unwrap_err()
+            .contains("whole number of at least 2 cells"));
+        assert!(Axis::parse("interval_factor=linear:2,10,-3").is_err());
+        assert!(Axis::parse("engine=list:1").is_err());
+        assert!(Axis::parse("bogus=list:1").is_err());
+    }
+
+    #[test]
+    fn cells_are_row_major() {
+        let axes = vec![
+            Axis::parse("max_lapses=list:1,2").unwrap(),
+            Axis::parse("days=list:10,20,30").unwrap(),
+        ];
+        assert_eq!(cell_count(&axes), 6);
+        assert_eq!(cell_coords(&axes, 4), vec![1, 1]);
+        let params = cell_params(&Params::default(), &axes, 5);
+        assert_eq!((params.max_lapses, params.days), (2, 30));
+    }
+
+    fn sampling(flags: &[&str]) -> Result<Sampling, String> {
+        let args = ["sweep"].iter().chain(flags).map(|s| s.to_string());
+        Sampling::from_args(&mut cli::Args::parse(args)?, 1)
+    }
+
+    #[test]
+    fn adaptive_sampling_needs_room_to_grow() {
+        let s = sampling(&[]).unwrap();
+        assert_eq!((s.replicates, s.tolerance), (1, None));
+        let s = sampling(&["--tolerance", "0.5"]).unwrap();
+        assert_eq!((s.replicates, s.max_replicates), (4, 64));
+        let s = sampling(&["--tolerance", "0.5", "--replicates", "1"]).unwrap();
+        assert_eq!(s.replicates, 2);
+        assert!(sampling(&["--tolerance", "0"]).is_err());
+        assert!(sampling(&["--tolerance", "0.5", "--max-replicates", "3"]).is_err());
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_at_the_tolerance() {
+        let params = Params {
+            engine: Engine::MonteCarlo,
+            days: 30,
+            seed: Some(7),
+            ..Params::default()
+        };
+        let sampling = |tolerance| Sampling {
+            tolerance: Some(tolerance),
+            max_replicates: 6,
+            ..Sampling::fixed(2)
+        };
+        // Nothing is that precise, so every replicate is used.
+        assert_eq!(sampling(1e-9).simulate(&params).len(), 6);
+        // Anything is precise enough.
+        assert_eq!(sampling(1e9).simulate(&params).len(), 2);
+        // Seeded replicates repeat exactly.
+        let runs = |s: Sampling| -> Vec<f32> {
+            s.simulate(&params)
+                .iter()
+                .map(|m| m.cards_learned_per_hour)
+                .collect()
+        };
+        assert_eq!(runs(Sampling::fixed(3)), runs(Sampling::fixed(3)));
+    }
+
+    /// A sweep over two axes whose cells hold their own index.
+    fn data() -> SweepData {
+        let axes = vec![
+            Axis::parse("max_lapses=list:1,2").unwrap(),
+            Axis::parse("days=list:10,20,30").unwrap(),
+        ];
+        let cells = |offset: f32| -> Vec<Metrics> {
+            (0..6)
+                .map(|i| {
+                    let mut m = Metrics::default();
+                    for name in METRIC_NAMES {
+                        *m.get_mut(name).unwrap() = i as f32 + offset;
+                    }
+                    m
+                })
+                .collect()
+        };
+        SweepData {
+            params: Params::default(),
+            axes,
+            results: cells(0.5),
+            std_errs: Some(cells(0.25)),
+            samples: Some(vec![2, 3, 4, 5, 6, 7]),
+            starting_deck: None,
+        }
+    }
+
+    #[test]
+    fn data_files_round_trip() {
+        // The deck is only recorded, so needn't exist to draw the data.
+        let data = SweepData {
+            starting_deck: Some("/nonexistent/collection.anki2".to_string()),
+            ..data()
+        };
+        let path = ::std::env::temp_dir().join("anki_sim_test_data.toml");
+        let path = path.to_str().unwrap();
+        data.save(path).unwrap();
+        let loaded = SweepData::load(path);
+        ::std::fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+        for (a, b) in loaded.axes.iter().zip(&data.axes) {
+            assert_eq!((&a.name, &a.values), (&b.name, &b.values));
+        }
+        for name in METRIC_NAMES {
+            let values =
+                |v: &[Metrics]| -> Vec<f32> { v.iter().map(|m| m.get(name).unwrap()).collect() };
+            assert_eq!(values(&loaded.results), values(&data.results));
+            assert_eq!(
+                values(loaded.std_errs.as_ref().unwrap()),
+                values(data.std_errs.as_ref().unwrap())
+            );
+        }
+        assert_eq!(loaded.samples, data.samples);
+        assert_eq!(loaded.starting_deck, data.starting_deck);
+    }
+
+    #[test]
+    fn rejects_short_metrics() {
+        let path = ::std::env::temp_dir().join("anki_sim_test_short.toml");
+        let path = path.to_str().unwrap();
+        ::std::fs::write(
+            path,
+            "[axis.days]\nlist = [10, 20]\n\n[metric.known_cards]\nvalues = [1]\n",
+        )
+        .unwrap();
+        let loaded = SweepData::load(path);
+        ::std::fs::remove_file(path).unwrap();
+        match loaded {
+            Err(e) => assert!(e.contains("1 values for 2 cells"), "{}", e),
+            Ok(_) => panic!("loaded a short metric"),
+        }
+    }
+
+    #[test]
+    fn reorder_swaps_axes() {
+        let data = data();
+        let swapped = data.reorder(&[1, 0]);
+        assert_eq!(swapped.axes[0].name, "days");
+        // The cell at days 20 and max_lapses 2 was index 4 and is now 3.
+        assert_eq!(swapped.results[3].get("known_cards"), Some(4.5));
+        assert_eq!(swapped.samples, Some(vec![2, 5, 3, 6, 4, 7]));
+        let back = swapped.reorder(&[1, 0]);
+        assert_eq!(back.samples, data.samples);
+    }
+
+    #[test]
+    fn seeds_round_trip_exactly() {
+        let data = SweepData {
+            params: Params {
+                seed: Some((1 << 53) + 1),
+                ..Params::default()
+            },
+            axes: vec![Axis::parse("max_lapses=list:4").unwrap()],
+            results: vec![Metrics::default()],
+            std_errs: None,
+            samples: None,
+            starting_deck: None,
+        };
+        let path = ::std::env::temp_dir().join("anki_sim_test_seed.toml");
+        let path = path.to_str().unwrap();
+        data.save(path).unwrap();
+        let loaded = SweepData::load(path);
+        ::std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.unwrap().params.seed, Some((1 << 53) + 1));
+    }
+}